@@ -1,22 +1,45 @@
+use cosmwasm_std::{entry_point, to_json_binary, Addr, Binary, Deps, DepsMut, Env, HexBinary, IbcMsg, IbcOrder, MessageInfo, Order, Reply, Response, StdError, StdResult, Storage, SubMsg, SubMsgResult, Timestamp};
 
-use cosmwasm_std::{entry_point, to_binary, to_json_binary, Binary, Deps, DepsMut, Env, IbcMsg, IbcTimeout, MessageInfo, Reply, Response, StdResult, Storage, Timestamp};
-
-use crate::{error::ContractError, ibc::check_dirty, msg::{ExecuteMsg, GetTxResponse, InstantiateMsg, Op, QueryMsg}, state::{self, State, Transaction, ABORTED_MAP, COMMITTED_MAP, DIRTY_VOTES_MAP, END_TIME, PREPARE_VOTES_MAP, START_TIME, STATE}, utils::{check_queue_top, get_seconds_diff, get_timeout, remove_all_pending_elements}};
-use cw2::set_contract_version;
+use crate::{error::ContractError, ibc::{abort_txs, check_dirty, commit_everywhere, deactivate_peer, execute_locally, local_msgs, prune_aborted, query_decision, remove_peer, upon_dirty_success, upon_failure}, msg::{CanPrepareResponse, Confirmation, ExecuteMsg, GetTxResponse, InstantiateMsg, ListTxsResponse, MigrateMsg, Op, QueryMsg, SagaStepResponse, ValidatorQueryMsg}, saga::{start_saga, step_msgs, step_reply}, state::{txs, Config, LegacyState, Protocol, Role, State, TimeoutPolicy, Transaction, TxId, TxInfo, TxStatus, CHAINS, CHANNELS, CONFIG, CONFIRMATIONS, DECISION_ACKS, DIRTY_VOTERS, DIRTY_VOTES_MAP, LEGACY_ORIGIN, LEGACY_STATE, LOCAL_RUN, PENDING_COMPENSATIONS, PENDING_REPLIES, PREPARE_VOTERS, PREPARE_VOTES_MAP, PRE_COMMIT_VOTERS, PRE_COMMIT_VOTES_MAP, SAGA_REPLIES, SAGA_STEPS, STATE, TX_COUNTER, TX_DIGESTS, TX_PAYLOADS}, utils::{add_vote, enqueue, recount_votes, get_seconds_diff, packet_data, tick, transition_tx, tx_channels, tx_digest, tx_role, tx_timeout}};
+use cw2::{get_contract_version, set_contract_version};
+use cw_storage_plus::Bound;
 
 const CONTRACT_NAME: &str = "crates.io:simple-storage";
 const CONTRACT_VERSION: &str = env!("CARGO_PKG_VERSION");
 
+// settings for pagination
+const MAX_LIMIT: u32 = 30;
+const DEFAULT_LIMIT: u32 = 10;
+
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn instantiate(
     deps: DepsMut,
-    env: Env,
+    _env: Env,
     info: MessageInfo,
     msg: InstantiateMsg,
 ) -> Result<Response, ContractError> {
     // ...
-    let state = State::new(msg.chain_id, env.block.time);
+    let packet_timeout = msg.packet_timeout.unwrap_or_default();
+    if !packet_timeout.is_valid() {
+        return Err(ContractError::InvalidTimeout {});
+    }
+    if msg.chain_id == LEGACY_ORIGIN {
+        return Err(ContractError::ReservedChainId { chain_id: msg.chain_id });
+    }
+    let state = State::new(msg.chain_id);
     STATE.save(deps.storage, &state)?;
+    let config = Config {
+        admin: info.sender.clone(),
+        channel_order: msg.channel_order.unwrap_or(IbcOrder::Unordered),
+        allowed_ports: msg.allowed_ports.unwrap_or_default(),
+        allowed_connections: msg.allowed_connections.unwrap_or_default(),
+        validator: msg.validator.map(|addr| deps.api.addr_validate(&addr)).transpose()?,
+        packet_timeout,
+        content_addressed: msg.content_addressed.unwrap_or(false),
+        protocol: msg.protocol.unwrap_or_default(),
+        three_phase_timeout: msg.three_phase_timeout,
+    };
+    CONFIG.save(deps.storage, &config)?;
     set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
     Ok(Response::new()
         .add_attribute("method", "instantiate")
@@ -34,6 +57,33 @@ pub fn execute(
         ExecuteMsg::Input {value} => {
             handle_execute_input(deps, env, info, value)
         },
+        ExecuteMsg::UpdateConfig { channel_order, allowed_ports, allowed_connections, packet_timeout, content_addressed } => {
+            handle_execute_update_config(deps, info, channel_order, allowed_ports, allowed_connections, packet_timeout, content_addressed)
+        },
+        ExecuteMsg::SetValidator { validator } => {
+            handle_execute_set_validator(deps, info, validator)
+        },
+        ExecuteMsg::TransferAdmin { admin } => {
+            handle_execute_transfer_admin(deps, info, admin)
+        },
+        ExecuteMsg::RemovePeer { chain_id } => {
+            handle_execute_remove_peer(deps, env, info, chain_id)
+        },
+        ExecuteMsg::SetPeerActive { chain_id, active } => {
+            handle_execute_set_peer_active(deps, env, info, chain_id, active)
+        },
+        ExecuteMsg::ExecuteLocal { tx_id, compensation } => {
+            handle_execute_local(deps, env, info, tx_id, compensation)
+        },
+        ExecuteMsg::ExecuteStep { tx_id, step, compensation } => {
+            handle_execute_step(deps, env, info, tx_id, step, compensation)
+        },
+        ExecuteMsg::Resolve { tx_id } => {
+            handle_execute_resolve(deps, env, tx_id)
+        },
+        ExecuteMsg::Crank { limit } => {
+            handle_execute_crank(deps, env, limit)
+        },
     }
 
 }
@@ -41,16 +91,19 @@ pub fn execute(
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn query(
     deps: Deps, 
-    env: Env, 
+    _env: Env, 
     msg: QueryMsg,
 ) -> StdResult<Binary> {
     // ...
     match msg{
-        QueryMsg::GetTx {}=> to_json_binary(&handle_query_gettx(deps)?),
+        QueryMsg::GetTx {tx_id}=> to_json_binary(&handle_query_gettx(deps, tx_id)?),
         QueryMsg::GetChainId {} => to_json_binary(&handle_query_getchainid(deps)?),
         QueryMsg::GetNodeNumber{} => to_json_binary(&handle_query_getnodenumber(deps)?),
+        QueryMsg::GetConfig{} => to_json_binary(&CONFIG.load(deps.storage)?),
         QueryMsg::GetDirtyTx {} => to_json_binary(&handle_query_getdirtytx(deps)?),
         QueryMsg::GetPrepareTx {} => to_json_binary(&handle_query_getpreparetx(deps)?),
+        QueryMsg::ListTxs { status, start_after, limit } => to_json_binary(&handle_query_listtxs(deps, status, start_after, limit)?),
+        QueryMsg::GetTxByDigest { digest } => to_json_binary(&handle_query_gettxbydigest(deps, digest)?),
 
     }
 
@@ -75,7 +128,7 @@ fn handle_query_getnodenumber(
 
 fn handle_query_getpreparetx(
     deps: Deps,
-)-> StdResult<Vec<u32>>{
+)-> StdResult<Vec<TxId>>{
     let state = STATE.load(deps.storage)?;
     Ok(state.prepare_tx_queue)
     
@@ -83,7 +136,7 @@ fn handle_query_getpreparetx(
 
 fn handle_query_getdirtytx(
     deps: Deps,
-)-> StdResult<Vec<u32>>{
+)-> StdResult<Vec<TxId>>{
     let state = STATE.load(deps.storage)?;
     Ok(state.dirty_tx_queue)
     
@@ -91,52 +144,299 @@ fn handle_query_getdirtytx(
 
 fn handle_query_gettx(
     deps:Deps,
+    tx_id: TxId,
 )-> StdResult<GetTxResponse>{
-    // let start_time = START_TIME.load(deps.storage, val)?;
-    // let end_time = END_TIME.load(deps.storage, val)?;
-    let state = STATE.load(deps.storage)?;
-    // load all the fields in gettxresponse in the contract storage, don't load from the State struct, but from the cosmwasmstd states
-   
-    // let time_in_seconds = get_seconds_diff(&state.start_time, &state.end_time.unwrap());
+    let tx = txs().load(deps.storage, tx_id)?;
+    tx_response(deps, tx_id, tx)
+}
+
+fn handle_query_listtxs(
+    deps: Deps,
+    status: Option<TxStatus>,
+    start_after: Option<TxId>,
+    limit: Option<u32>,
+)-> StdResult<ListTxsResponse>{
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map(Bound::exclusive);
+    let page = match status {
+        Some(status) => txs()
+            .idx
+            .status
+            .prefix(status.as_str().to_string())
+            .range(deps.storage, start, None, Order::Ascending)
+            .take(limit)
+            .collect::<StdResult<Vec<_>>>()?,
+        None => txs()
+            .range(deps.storage, start, None, Order::Ascending)
+            .take(limit)
+            .collect::<StdResult<Vec<_>>>()?,
+    };
+    let txs = page
+        .into_iter()
+        .map(|(tx_id, tx)| tx_response(deps, tx_id, tx))
+        .collect::<StdResult<Vec<_>>>()?;
+    Ok(ListTxsResponse { txs })
+}
+
+fn tx_response(
+    deps: Deps,
+    tx_id: TxId,
+    tx: TxInfo,
+)-> StdResult<GetTxResponse>{
+    // load all the fields of the given transaction from the per-tx maps instead of the global State struct
+    let end_time = tx.committed_at.or(tx.aborted_at);
+    let time = match (tx.submitted_at, end_time) {
+        (Some(start), Some(end)) => Some(get_seconds_diff(&start, &end)),
+        _ => None,
+    };
+    let dirty_voters = DIRTY_VOTERS
+        .prefix(tx_id)
+        .keys(deps.storage, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<u32>>>()?;
+    let prepare_voters = PREPARE_VOTERS
+        .prefix(tx_id)
+        .keys(deps.storage, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<u32>>>()?;
+    let pre_commit_voters = PRE_COMMIT_VOTERS
+        .prefix(tx_id)
+        .keys(deps.storage, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<u32>>>()?;
+    let decision_acks = DECISION_ACKS
+        .prefix(tx_id)
+        .keys(deps.storage, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<u32>>>()?;
+    let confirmations = CONFIRMATIONS
+        .sub_prefix(tx_id)
+        .range(deps.storage, None, None, Order::Ascending)
+        .map(|item| item.map(|((phase, chain_id), status)| Confirmation { chain_id, phase, status }))
+        .collect::<StdResult<Vec<_>>>()?;
+    let steps = SAGA_STEPS
+        .prefix(tx_id)
+        .range(deps.storage, None, None, Order::Ascending)
+        .map(|item| item.map(|(step, info)| SagaStepResponse { step, chain_id: info.chain_id, status: info.status, error: info.error }))
+        .collect::<StdResult<Vec<_>>>()?;
 
     Ok(
         GetTxResponse{
-            dirty_votes: Some(state.dirty_votes),
-            prepare_votes: Some(state.prepare_votes),
-            committed: Some(state.committed),
-            aborted: Some(state.aborted),
-            start_time: state.start_time,
-            end_time: state.end_time,
-            time: Some(state.time),
-
-            // time: Some(time_in_seconds),
+            tx_id,
+            status: tx.status,
+            submitter: tx.submitter,
+            dirty_votes: DIRTY_VOTES_MAP.may_load(deps.storage, tx_id)?.unwrap_or_default(),
+            prepare_votes: PREPARE_VOTES_MAP.may_load(deps.storage, tx_id)?.unwrap_or_default(),
+            pre_commit_votes: PRE_COMMIT_VOTES_MAP.may_load(deps.storage, tx_id)?.unwrap_or_default(),
+            dirty_voters,
+            prepare_voters,
+            pre_commit_voters,
+            seen_at: tx.seen_at,
+            submitted_at: tx.submitted_at,
+            prepared_at: tx.prepared_at,
+            pre_committed_at: tx.pre_committed_at,
+            committed_at: tx.committed_at,
+            aborted_at: tx.aborted_at,
+            executed_at: tx.executed_at,
+            confirmations,
+            decision_acks,
+            error: tx.error,
+            digest: tx.digest,
+            steps,
+            time,
         }
     )
-    
-    // Ok(
-    //     GetTxResponse{
-    //         dirty_votes: Some(state.dirty_votes),
-    //         prepare_votes: Some(state.prepare_votes),
-    //         committed: Some(state.committed),
-    //         aborted: Some(state.aborted),
-    //         start_time: state.start_time,
-    //         end_time: state.end_time,
-    //         time: Some(state.time),
-
-    //         // time: Some(time_in_seconds),
-    //     }
-    
-    // )
 
 }
+fn handle_query_gettxbydigest(
+    deps: Deps,
+    digest: HexBinary,
+) -> StdResult<GetTxResponse> {
+    let tx_id = TX_DIGESTS.load(deps.storage, digest.as_slice())?;
+    handle_query_gettx(deps, tx_id)
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn migrate(
+    deps: DepsMut,
+    env: Env,
+    msg: MigrateMsg,
+) -> Result<Response, ContractError> {
+    let stored = get_contract_version(deps.storage)?;
+    if stored.contract != CONTRACT_NAME {
+        return Err(ContractError::WrongContract { expected: CONTRACT_NAME.to_string(), actual: stored.contract });
+    }
+    if parse_version(&stored.version)? > parse_version(CONTRACT_VERSION)? {
+        return Err(ContractError::CannotDowngrade { stored: stored.version, current: CONTRACT_VERSION.to_string() });
+    }
+
+    // the single State of 0.0.x is split into the new State and one record per queued transaction
+    let migrated = match LEGACY_STATE.load(deps.storage) {
+        Ok(legacy) => migrate_legacy_state(deps.storage, legacy, env.block.time)?,
+        Err(_) => 0,
+    };
+    // the chains of the channels used to be found by scanning CHANNELS
+    let channels = CHANNELS
+        .range(deps.storage, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<(u32, String)>>>()?;
+    for (chain_id, channel_id) in channels {
+        CHAINS.save(deps.storage, &channel_id, &chain_id)?;
+    }
+    if CONFIG.may_load(deps.storage)?.is_none() {
+        let admin = msg.admin.ok_or(ContractError::MissingAdmin {})?;
+        let config = Config {
+            admin: deps.api.addr_validate(&admin)?,
+            channel_order: IbcOrder::Unordered,
+            allowed_ports: vec![],
+            allowed_connections: vec![],
+            validator: None,
+            packet_timeout: TimeoutPolicy::default(),
+            content_addressed: false,
+            protocol: Protocol::AllToAll,
+            three_phase_timeout: None,
+        };
+        CONFIG.save(deps.storage, &config)?;
+    }
+
+    set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
+    Ok(Response::new()
+        .add_attribute("method", "migrate")
+        .add_attribute("from_version", stored.version)
+        .add_attribute("to_version", CONTRACT_VERSION)
+        .add_attribute("migrated_txs", migrated.to_string()))
+}
+
+fn migrate_legacy_state(
+    store: &mut dyn Storage,
+    legacy: LegacyState,
+    now: Timestamp,
+) -> Result<usize, ContractError> {
+    // the legacy ids were chosen by the users and shared by every chain, so they keep a common origin
+    // that new transactions never use
+    let key = |seq: &u32| TxId { origin: LEGACY_ORIGIN, seq: *seq };
+    // the legacy transactions carry no payload and their votes were not kept per transaction, so the ones
+    // still in flight could never be decided; every chain aborts them alike and starts with empty queues
+    let state = State {
+        node_number: legacy.node_number,
+        chain_id: legacy.chain_id,
+        channel_ids: legacy.channel_ids,
+        dirty_tx_queue: vec![],
+        prepare_tx_queue: vec![],
+        lamport: 0,
+    };
+    STATE.save(store, &state)?;
+
+    let queues = [
+        (legacy.dirty_tx_queue, TxStatus::Dirty),
+        (legacy.prepare_tx_queue, TxStatus::Prepared),
+        (legacy.commit_tx_queue, TxStatus::Committed),
+    ];
+    // 0.0.x only kept the times of the last input and the last outcome, which say nothing about each transaction,
+    // so the record starts at the migration and only the abortion it performs is dated
+    let mut migrated = 0;
+    for (queue, status) in queues {
+        for seq in queue {
+            let mut tx = TxInfo::new(now);
+            if status == TxStatus::Committed {
+                tx.status = TxStatus::Committed;
+            } else {
+                tx.status = TxStatus::Aborted;
+                tx.aborted_at = Some(now);
+                tx.error = Some(format!("aborted by the migration while {}", status));
+            }
+            txs().save(store, key(&seq), &tx)?;
+            migrated += 1;
+        }
+    }
+    Ok(migrated)
+}
+
+// split a "major.minor.patch" version into numbers that compare in release order
+fn parse_version(version: &str) -> StdResult<Vec<u64>> {
+    version
+        .split('.')
+        .map(|part| part.parse::<u64>().map_err(|_| StdError::generic_err(format!("invalid version: {}", version))))
+        .collect()
+}
+
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn reply(
     deps: DepsMut, 
     env: Env,
     msg: Reply
-) -> StdResult<Response> {
+) -> Result<Response, ContractError> {
     // ...
-    Ok(Response::default())
+    LOCAL_RUN.remove(deps.storage);
+    if let Some(saga_reply) = SAGA_REPLIES.may_load(deps.storage, msg.id)? {
+        SAGA_REPLIES.remove(deps.storage, msg.id);
+        let error = match msg.result {
+            SubMsgResult::Ok(_) => None,
+            SubMsgResult::Err(error) => Some(error),
+        };
+        let msgs = step_reply(deps.storage, &env, saga_reply, error.clone())?;
+        let response = Response::new()
+            .add_submessages(msgs)
+            .add_attribute("action", "reply")
+            .add_attribute("tx_id", saga_reply.tx_id.to_string())
+            .add_attribute("step", saga_reply.step.to_string())
+            .add_attribute("compensation", saga_reply.compensation.to_string());
+        return Ok(match error {
+            Some(error) => response.add_attribute("success", "false").add_attribute("error", error),
+            None => response.add_attribute("success", "true"),
+        });
+    }
+    // the transaction stays aborted whether its compensation succeeds or not
+    if let Some(tx_id) = PENDING_COMPENSATIONS.may_load(deps.storage, msg.id)? {
+        PENDING_COMPENSATIONS.remove(deps.storage, msg.id);
+        let response = Response::new()
+            .add_attribute("action", "reply")
+            .add_attribute("tx_id", tx_id.to_string())
+            .add_attribute("compensation", "true");
+        let error = match msg.result {
+            SubMsgResult::Ok(_) => None,
+            SubMsgResult::Err(error) => Some(error),
+        };
+        if let Some(error) = &error {
+            let mut tx = txs().load(deps.storage, tx_id)?;
+            tx.error = Some(error.clone());
+            txs().save(deps.storage, tx_id, &tx)?;
+        }
+        prune_aborted(deps.storage, tx_id)?;
+        return Ok(match error {
+            Some(error) => response.add_attribute("success", "false").add_attribute("error", error),
+            None => response.add_attribute("success", "true"),
+        });
+    }
+    let tx_id = PENDING_REPLIES.load(deps.storage, msg.id)?;
+    PENDING_REPLIES.remove(deps.storage, msg.id);
+    let response = Response::new()
+        .add_attribute("action", "reply")
+        .add_attribute("tx_id", tx_id.to_string());
+    let mut tx = txs().load(deps.storage, tx_id)?;
+    match msg.result {
+        SubMsgResult::Ok(_) => {
+            tx.executed_at = Some(env.block.time);
+            txs().save(deps.storage, tx_id, &tx)?;
+            // the local portion ran, so this chain votes for the transaction, unless it was aborted meanwhile,
+            // in which case the local portion is undone right away
+            let msgs = match tx.status {
+                TxStatus::Aborted => vec![execute_locally(deps.storage, &env, tx_id, true)?],
+                _ => upon_dirty_success(deps.storage, &env, tx_id)?,
+            };
+            Ok(response
+                .add_submessages(msgs)
+                .add_attribute("success", "true"))
+        },
+        SubMsgResult::Err(error) => {
+            // the local portion could not run, and no chain can commit the transaction without the vote of this one
+            tx.error = Some(error.clone());
+            txs().save(deps.storage, tx_id, &tx)?;
+            let msgs = match tx.status.is_final() {
+                true => vec![],
+                false => abort_txs(deps.storage, &env, &[tx_id])?,
+            };
+            Ok(response
+                .add_submessages(msgs)
+                .add_attribute("success", "false")
+                .add_attribute("error", error))
+        },
+    }
 }
 
 
@@ -144,47 +444,393 @@ pub fn handle_execute_input (
     
     deps: DepsMut,
     env: Env,
-    _info: MessageInfo,
-    input: Transaction,
+    info: MessageInfo,
+    mut input: Transaction,
 ) -> Result<Response, ContractError>{
-    let timeout: IbcTimeout = get_timeout(&env);
-    // if the txid index in the start time is empty, fill it as the current block time
+    // the id is handed out by this chain instead of being chosen by the sender
+    let state = STATE.load(deps.storage)?;
+    let seq = TX_COUNTER.may_load(deps.storage)?.unwrap_or_default() + 1;
+    TX_COUNTER.save(deps.storage, &seq)?;
+    let tx_id = TxId { origin: state.chain_id, seq };
+    if txs().has(deps.storage, tx_id) {
+        return Err(ContractError::TxExists { tx_id });
+    }
+    if input.expires_at.is_some_and(|expires_at| expires_at.is_expired(&env.block)) {
+        return Err(ContractError::Expired { tx_id });
+    }
+    if input.packet_timeout.as_ref().is_some_and(|policy| !policy.is_valid()) {
+        return Err(ContractError::InvalidTimeout {});
+    }
+    // the participants of a saga are the chains running its steps
+    if input.saga {
+        if input.msgs.is_empty() {
+            return Err(ContractError::EmptySaga {});
+        }
+        input.participants = input.msgs.iter().map(|chain_msgs| chain_msgs.chain_id).collect();
+    }
+    // this chain always takes part in the transactions submitted here, and every other participant has to be an active peer
+    if !input.participants.is_empty() {
+        input.participants.push(state.chain_id);
+        input.participants.sort_unstable();
+        input.participants.dedup();
+        for chain_id in input.participants.iter().filter(|chain_id| **chain_id != state.chain_id) {
+            let channel_id = CHANNELS.may_load(deps.storage, *chain_id)?
+                .ok_or(ContractError::UnknownChain { chain_id: *chain_id })?;
+            if !state.channel_ids.contains(&channel_id) {
+                return Err(ContractError::InactivePeer { chain_id: *chain_id });
+            }
+        }
+    }
+
+    // the submission is ordered against every other transaction by the lamport clock of this chain
+    input.lamport = tick(deps.storage)?;
 
-   
-    // push the initial transaction into the dirty tx queue
+    let digest = if CONFIG.load(deps.storage)?.content_addressed {
+        Some(tx_digest(tx_id, &input)?)
+    } else {
+        None
+    };
+    let (msgs, refusal) = if input.saga {
+        start_saga(deps, &env, tx_id, input, digest, info.sender)?
+    } else {
+        join_tx(deps, &env, tx_id, input, digest, Some(info.sender))?
+    };
+    let mut response = Response::new()
+        .add_submessages(msgs)
+        .set_data(to_json_binary(&tx_id)?)
+        .add_attribute("action", "handle_execute_input")
+        .add_attribute("tx_id", tx_id.to_string());
+    response = match refusal {
+        Some(reason) => response.add_attribute("vote", "no").add_attribute("reason", reason),
+        None => response.add_attribute("vote", "yes"),
+    };
+    Ok(response)
+
+}
+
+// take part in a transaction, either submitted here or announced by the chain it originates from;
+// this chain votes yes by broadcasting its dirty success, or no by aborting the transaction everywhere, in which case the reason is returned
+pub fn join_tx(
+    deps: DepsMut,
+    env: &Env,
+    tx_id: TxId,
+    payload: Transaction,
+    digest: Option<HexBinary>,
+    submitter: Option<Addr>,
+) -> Result<(Vec<SubMsg>, Option<String>), ContractError>{
+    let mut tx = transition_tx(deps.storage, tx_id, TxStatus::Dirty, env.block.time)?;
+    tx.submitter = submitter;
+    tx.digest = digest.clone();
+    tx.participants = Some(payload.participants.clone());
+    txs().save(deps.storage, tx_id, &tx)?;
+    if let Some(digest) = &digest {
+        TX_DIGESTS.save(deps.storage, digest.as_slice(), &tx_id)?;
+    }
+    TX_PAYLOADS.save(deps.storage, tx_id, &payload)?;
+    recount_votes(deps.storage, tx_id)?;
+
+    // the application may refuse the transaction, and a peer may only hear about it once it has expired
+    let refusal = if payload.expires_at.is_some_and(|expires_at| expires_at.is_expired(&env.block)) {
+        Some(ContractError::Expired { tx_id }.to_string())
+    } else {
+        check_can_prepare(deps.as_ref(), &payload)?
+    };
+    if let Some(reason) = refusal {
+        tx.error = Some(reason.clone());
+        txs().save(deps.storage, tx_id, &tx)?;
+        let msgs = upon_failure(deps.storage, env, tx_id)?;
+        return Ok((msgs, Some(reason)));
+    }
+
+    // push the transaction into the dirty tx queue and count the local dirty vote
     let mut state = STATE.load(deps.storage)?;
-    state.dirty_tx_queue.push(input.tx_id);
-    state.start_time=env.block.time;
-    state.dirty_votes+=1;
+    enqueue(deps.storage, &mut state.dirty_tx_queue, tx_id)?;
     STATE.save(deps.storage, &state)?;
+    add_vote(deps.storage, &DIRTY_VOTES_MAP, &DIRTY_VOTERS, tx_id, state.chain_id, env.block.time)?;
 
 
-    // generate a dirty success message
-    let mut msgs: Vec<IbcMsg> = Vec::new();
-        for channel_id in state.channel_ids.iter() {
-            let packet = Op::DirtySuccess { value: input.tx_id };
+    // generate a dirty success message, the peers learn the payload from the origin;
+    // a coordinator only proposes the transaction, its vote comes once it has collected the others
+    let timeout = tx_timeout(deps.storage, env, tx_id)?;
+    let channel_ids = tx_channels(deps.storage, tx_id)?;
+    let packet = match tx_role(deps.storage, tx_id)? {
+        Role::Coordinator => Op::Propose { value: tx_id, tx: payload },
+        Role::Peer if tx_id.origin == state.chain_id => Op::DirtySuccess { value: tx_id, tx: Some(payload) },
+        Role::Peer | Role::Participant => Op::DirtySuccess { value: tx_id, tx: None },
+    };
+    let data = packet_data(deps.storage, &packet)?;
+    let mut msgs: Vec<SubMsg> = Vec::new();
+        for channel_id in channel_ids.iter() {
             let ibc_msg = IbcMsg::SendPacket {
                 channel_id: channel_id.to_string(),
-                data: to_json_binary(&packet)?,
+                data: data.clone(),
                 timeout: timeout.clone(),
             };
             // 将新创建的消息添加到msgs向量中
-            msgs.push(ibc_msg);
+            msgs.push(SubMsg::new(ibc_msg));
         }
-    
-    let response = Response::new()
+
+    // the votes of the other chains may have arrived before this chain joined
+    msgs.append(&mut check_dirty(deps.storage, env.clone(), tx_id)?);
+    Ok((msgs, None))
+}
+
+
+// ask the validator contract whether this chain can take part in the transaction, and return the reason if it cannot
+pub fn check_can_prepare(
+    deps: Deps,
+    tx: &Transaction,
+) -> StdResult<Option<String>> {
+    let validator = match CONFIG.load(deps.storage)?.validator {
+        Some(validator) => validator,
+        None => return Ok(None),
+    };
+    let query = ValidatorQueryMsg::CanPrepare { tx: tx.clone() };
+    // a validator that cannot answer is taken as a no
+    match deps.querier.query_wasm_smart::<CanPrepareResponse>(validator, &query) {
+        Ok(response) if response.can_prepare => Ok(None),
+        Ok(response) => Ok(Some(response.reason.unwrap_or_else(|| "refused by validator".to_string()))),
+        Err(error) => Ok(Some(error.to_string())),
+    }
+}
+
+fn check_admin(
+    deps: Deps,
+    info: &MessageInfo,
+) -> Result<Config, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    if info.sender != config.admin {
+        return Err(ContractError::Unauthorized {});
+    }
+    Ok(config)
+}
+
+pub fn handle_execute_update_config(
+    deps: DepsMut,
+    info: MessageInfo,
+    channel_order: Option<IbcOrder>,
+    allowed_ports: Option<Vec<String>>,
+    allowed_connections: Option<Vec<String>>,
+    packet_timeout: Option<TimeoutPolicy>,
+    content_addressed: Option<bool>,
+) -> Result<Response, ContractError>{
+    let mut config = check_admin(deps.as_ref(), &info)?;
+    if let Some(channel_order) = channel_order {
+        config.channel_order = channel_order;
+    }
+    if let Some(allowed_ports) = allowed_ports {
+        config.allowed_ports = allowed_ports;
+    }
+    if let Some(allowed_connections) = allowed_connections {
+        config.allowed_connections = allowed_connections;
+    }
+    if let Some(packet_timeout) = packet_timeout {
+        if !packet_timeout.is_valid() {
+            return Err(ContractError::InvalidTimeout {});
+        }
+        config.packet_timeout = packet_timeout;
+    }
+    if let Some(content_addressed) = content_addressed {
+        config.content_addressed = content_addressed;
+    }
+    CONFIG.save(deps.storage, &config)?;
+    Ok(Response::new().add_attribute("action", "handle_execute_update_config"))
+}
+
+pub fn handle_execute_set_validator(
+    deps: DepsMut,
+    info: MessageInfo,
+    validator: Option<String>,
+) -> Result<Response, ContractError>{
+    let mut config = check_admin(deps.as_ref(), &info)?;
+    config.validator = validator.map(|addr| deps.api.addr_validate(&addr)).transpose()?;
+    CONFIG.save(deps.storage, &config)?;
+    let validator = config.validator.map(String::from).unwrap_or_default();
+    Ok(Response::new()
+        .add_attribute("action", "handle_execute_set_validator")
+        .add_attribute("validator", validator))
+}
+
+pub fn handle_execute_transfer_admin(
+    deps: DepsMut,
+    info: MessageInfo,
+    admin: String,
+) -> Result<Response, ContractError>{
+    let mut config = check_admin(deps.as_ref(), &info)?;
+    config.admin = deps.api.addr_validate(&admin)?;
+    CONFIG.save(deps.storage, &config)?;
+    Ok(Response::new()
+        .add_attribute("action", "handle_execute_transfer_admin")
+        .add_attribute("admin", config.admin))
+}
+
+pub fn handle_execute_remove_peer(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    chain_id: u32,
+) -> Result<Response, ContractError>{
+    check_admin(deps.as_ref(), &info)?;
+    let channel_id = CHANNELS.may_load(deps.storage, chain_id)?
+        .ok_or(ContractError::UnknownChain { chain_id })?;
+    let (msgs, aborted) = remove_peer(deps.storage, &env, &channel_id)?;
+    Ok(Response::new()
+        .add_submessages(msgs)
+        .add_message(IbcMsg::CloseChannel { channel_id: channel_id.clone() })
+        .add_attribute("action", "handle_execute_remove_peer")
+        .add_attribute("chain_id", chain_id.to_string())
+        .add_attribute("channel_id", channel_id)
+        .add_attribute("aborted", aborted.len().to_string()))
+}
+
+pub fn handle_execute_set_peer_active(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    chain_id: u32,
+    active: bool,
+) -> Result<Response, ContractError>{
+    check_admin(deps.as_ref(), &info)?;
+    let channel_id = CHANNELS.may_load(deps.storage, chain_id)?
+        .ok_or(ContractError::UnknownChain { chain_id })?;
+    let mut msgs: Vec<SubMsg> = Vec::new();
+    let mut aborted: Vec<TxId> = Vec::new();
+    if active {
+        let mut state = STATE.load(deps.storage)?;
+        if !state.channel_ids.contains(&channel_id) {
+            state.channel_ids.push(channel_id);
+            state.node_number += 1;
+            STATE.save(deps.storage, &state)?;
+        }
+    } else {
+        (msgs, aborted) = deactivate_peer(deps.storage, &env, &channel_id, Some(chain_id))?;
+    }
+    Ok(Response::new()
+        .add_submessages(msgs)
+        .add_attribute("action", "handle_execute_set_peer_active")
+        .add_attribute("chain_id", chain_id.to_string())
+        .add_attribute("active", active.to_string())
+        .add_attribute("aborted", aborted.len().to_string()))
+}
+
+// only the contract runs the messages of a transaction, one submessage at a time: a message of the payload
+// calling back into the contract while they run is refused, since its sender is the contract as well
+fn start_local_run(
+    store: &mut dyn Storage,
+    env: &Env,
+    info: &MessageInfo,
+    tx_id: TxId,
+) -> Result<(), ContractError> {
+    if info.sender != env.contract.address || LOCAL_RUN.may_load(store)?.is_some() {
+        return Err(ContractError::Unauthorized {});
+    }
+    LOCAL_RUN.save(store, &tx_id)?;
+    Ok(())
+}
+
+pub fn handle_execute_local(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    tx_id: TxId,
+    compensation: bool,
+) -> Result<Response, ContractError>{
+    start_local_run(deps.storage, &env, &info, tx_id)?;
+    let state = STATE.load(deps.storage)?;
+    let msgs = local_msgs(deps.storage, state.chain_id, tx_id, compensation)?;
+    Ok(Response::new()
         .add_messages(msgs)
-        .add_attribute("action", "handle_execute_input")
-        .add_attribute("tx_id", input.tx_id.to_string());
-    Ok(response)
+        .add_attribute("action", "handle_execute_local")
+        .add_attribute("tx_id", tx_id.to_string())
+        .add_attribute("compensation", compensation.to_string()))
+}
 
-    // let msgs = check_dirty(deps.storage, env, input.tx_id, timeout).unwrap();
-    // // check if messages contains no ibcmsg
-    // if msgs.is_empty(){
-    //     return Ok(response);
-    // }else{
-    //     return Ok(response.add_messages(msgs));
+pub fn handle_execute_step(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    tx_id: TxId,
+    step: u32,
+    compensation: bool,
+) -> Result<Response, ContractError>{
+    start_local_run(deps.storage, &env, &info, tx_id)?;
+    let msgs = step_msgs(deps.storage, tx_id, step, compensation)?;
+    Ok(Response::new()
+        .add_messages(msgs)
+        .add_attribute("action", "handle_execute_step")
+        .add_attribute("tx_id", tx_id.to_string())
+        .add_attribute("step", step.to_string())
+        .add_attribute("compensation", compensation.to_string()))
+}
 
-    // }    
+pub fn handle_execute_resolve(
+    deps: DepsMut,
+    env: Env,
+    tx_id: TxId,
+) -> Result<Response, ContractError>{
+    let status = txs().load(deps.storage, tx_id)?.status;
+    if !matches!(status, TxStatus::Prepared | TxStatus::PreCommitted) {
+        return Err(ContractError::CannotResolve { tx_id, status });
+    }
+    let msgs = query_decision(deps.storage, &env, tx_id)?;
+    Ok(Response::new()
+        .add_submessages(msgs)
+        .add_attribute("action", "handle_execute_resolve")
+        .add_attribute("tx_id", tx_id.to_string()))
+}
 
+pub fn handle_execute_crank(
+    deps: DepsMut,
+    env: Env,
+    limit: Option<u32>,
+) -> Result<Response, ContractError>{
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let state = STATE.load(deps.storage)?;
+    let phase_timeout = CONFIG.load(deps.storage)?.three_phase_timeout;
+    let mut expired: Vec<TxId> = Vec::new();
+    let mut committed: Vec<TxId> = Vec::new();
+    // the queues are in lamport order rather than expiry order, so the whole of them is scanned,
+    // and only the number of transactions settled at once is limited
+    for tx_id in state.dirty_tx_queue.iter().chain(state.prepare_tx_queue.iter()) {
+        if expired.len() + committed.len() >= limit {
+            break;
+        }
+        let tx = txs().load(deps.storage, *tx_id)?;
+        let expires_at = TX_PAYLOADS.may_load(deps.storage, *tx_id)?.and_then(|tx| tx.expires_at);
+        // once this chain voted to prepare, a peer may already have committed, so only the decision settles the transaction;
+        // a pre-committed transaction is committed by the phase timeout below instead
+        let voted = match tx.status {
+            TxStatus::Prepared => PREPARE_VOTERS.has(deps.storage, (*tx_id, state.chain_id)),
+            TxStatus::PreCommitted => true,
+            _ => false,
+        };
+        if !voted && expires_at.is_some_and(|expires_at| expires_at.is_expired(&env.block)) {
+            expired.push(*tx_id);
+            continue;
+        }
+        // under the 3PC a chain never blocks: it commits on its own once everyone is known to be prepared, and aborts before that
+        if let Some(phase_timeout) = phase_timeout {
+            let entered_at = match tx.status {
+                TxStatus::Dirty => tx.submitted_at,
+                TxStatus::Prepared => tx.prepared_at,
+                TxStatus::PreCommitted => tx.pre_committed_at,
+                _ => None,
+            };
+            if entered_at.is_some_and(|entered_at| entered_at.plus_seconds(phase_timeout) <= env.block.time) {
+                match tx.status {
+                    TxStatus::PreCommitted => committed.push(*tx_id),
+                    _ => expired.push(*tx_id),
+                }
+            }
+        }
+    }
+    let mut msgs = abort_txs(deps.storage, &env, &expired)?;
+    for tx_id in committed.iter() {
+        msgs.append(&mut commit_everywhere(deps.storage, &env, *tx_id)?);
+    }
+    Ok(Response::new()
+        .add_submessages(msgs)
+        .add_attribute("action", "handle_execute_crank")
+        .add_attribute("aborted", expired.len().to_string())
+        .add_attribute("committed", committed.len().to_string()))
 }
@@ -1,10 +1,90 @@
-use cosmwasm_std::StdError;
+use cosmwasm_std::{IbcOrder, StdError};
 use thiserror::Error;
+
+use crate::state::{TxId, TxStatus};
 // Path: src/error.rs
 
 #[derive(Error, Debug)]
 pub enum ContractError {
     #[error("{0}")]
     Std (#[from] StdError),
-    
+
+    #[error("transaction {tx_id} cannot move from {from} to {to}")]
+    InvalidTransition { tx_id: TxId, from: TxStatus, to: TxStatus },
+
+    #[error("channel {channel_id} is not registered to any chain")]
+    UnknownChannel { channel_id: String },
+
+    #[error("transaction {tx_id} has already expired")]
+    Expired { tx_id: TxId },
+
+    #[error("transaction {tx_id} is {status}, only prepared transactions can be resolved")]
+    CannotResolve { tx_id: TxId, status: TxStatus },
+
+    #[error("transaction {tx_id} already exists")]
+    TxExists { tx_id: TxId },
+
+    #[error("only the origin of transaction {tx_id} can send this message, not chain {chain_id}")]
+    NotOrigin { tx_id: TxId, chain_id: u32 },
+
+    #[error("transaction {tx_id} does not match the digest it was announced with")]
+    InvalidDigest { tx_id: TxId },
+
+    #[error("chain {chain_id} does not take part in transaction {tx_id}")]
+    NotParticipant { tx_id: TxId, chain_id: u32 },
+
+    #[error("transaction {tx_id} is not committed on this chain")]
+    NotCommitted { tx_id: TxId },
+
+    #[error("transaction {tx_id} was refused: {reason}")]
+    Refused { tx_id: TxId, reason: String },
+
+    #[error("a saga needs at least one step")]
+    EmptySaga {},
+
+    #[error("transaction {tx_id} has no step {step} to run on this chain")]
+    InvalidStep { tx_id: TxId, step: u32 },
+
+    #[error("chain id {chain_id} is reserved for the transactions of 0.0.x")]
+    ReservedChainId { chain_id: u32 },
+
+    #[error("chain {chain_id} is already bound to channel {channel_id}")]
+    ChainBound { chain_id: u32, channel_id: String },
+
+    #[error("channel {channel_id} is already bound to chain {chain_id}")]
+    ChannelBound { channel_id: String, chain_id: u32 },
+
+    #[error("no channel is registered for chain {chain_id}")]
+    UnknownChain { chain_id: u32 },
+
+    #[error("chain {chain_id} is not an active member of the vote set")]
+    InactivePeer { chain_id: u32 },
+
+    #[error("unauthorized")]
+    Unauthorized {},
+
+    #[error("cannot migrate from contract {actual}, expected {expected}")]
+    WrongContract { expected: String, actual: String },
+
+    #[error("cannot migrate from version {stored} down to {current}")]
+    CannotDowngrade { stored: String, current: String },
+
+    #[error("an admin is required to migrate a contract without config")]
+    MissingAdmin {},
+
+    #[error("only {expected:?} channels are supported, got {actual:?}")]
+    InvalidIbcOrder { expected: IbcOrder, actual: IbcOrder },
+
+    #[error("channel version must be {expected}, got {actual}")]
+    InvalidIbcVersion { expected: String, actual: String },
+
+    #[error("counterparty port {port_id} is not allowed")]
+    UnauthorizedPort { port_id: String },
+
+    #[error("connection {connection_id} is not allowed")]
+    UnauthorizedConnection { connection_id: String },
+
+    #[error("a packet timeout needs a time or a height")]
+    InvalidTimeout {},
+
 }
\ No newline at end of file
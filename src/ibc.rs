@@ -1,22 +1,52 @@
-use std::fmt::format;
-
-use cosmwasm_std::{from_json, from_slice, to_binary, to_json_binary, Binary, ContractResult, Event, IbcMsg, IbcPacket, IbcTimeout, Storage, SubMsg, Timestamp};
-use cosmwasm_std::{entry_point, DepsMut, Env, IbcBasicResponse, IbcChannelCloseMsg, IbcChannelConnectMsg, IbcChannelOpenMsg, IbcPacketAckMsg, IbcPacketReceiveMsg, IbcPacketTimeoutMsg, IbcReceiveResponse, Never, Response, StdResult};
+use cosmwasm_std::{from_json, to_json_binary, Binary, ContractResult, CosmosMsg, Event, HexBinary, IbcMsg, Order, Storage, SubMsg, Timestamp};
+use cw_storage_plus::Map;
+use cosmwasm_std::{entry_point, DepsMut, Env, Ibc3ChannelOpenResponse, IbcBasicResponse, IbcChannelCloseMsg, IbcChannelConnectMsg, IbcChannelOpenMsg, IbcChannelOpenResponse, IbcPacketAckMsg, IbcPacketReceiveMsg, IbcPacketTimeoutMsg, IbcReceiveResponse, StdResult};
 
+use crate::contract::{check_can_prepare, join_tx};
 use crate::error::ContractError;
-use crate::msg::{AcknowledgementMsg, MsgQueueResponse, Op, WhoAmIResponse};
+use crate::msg::{AcknowledgementMsg, ExecuteMsg, MsgQueueResponse, Op, PacketData, WhoAmIResponse};
+use crate::saga::{on_step_result, receive_step};
 use crate::state::*;
-use crate::utils::{check_queue_top, get_timeout, remove_all_pending_elements};
+use crate::utils::{add_vote, call_self, chain_of_channel, check_queue_top, default_timeout, enqueue, load_tx, merge_clock, packet_data, transition_tx, tx_channels, tx_digest, tx_participants, tx_quorum, tx_role, tx_timeout};
+
 
 
+pub const IBC_VERSION: &str = "avalon-2";
 
 #[entry_point]
 pub fn ibc_channel_open(
-    _deps: DepsMut,
+    deps: DepsMut,
     _env: Env,
-    _msg: IbcChannelOpenMsg,        
-) -> StdResult<()>{
+    msg: IbcChannelOpenMsg,        
+) -> Result<IbcChannelOpenResponse, ContractError>{
+    let config = CONFIG.load(deps.storage)?;
+    let channel = msg.channel();
+    if channel.order != config.channel_order {
+        return Err(ContractError::InvalidIbcOrder { expected: config.channel_order, actual: channel.order.clone() });
+    }
+    // both ends have to speak the same protocol, in OpenInit and OpenTry alike;
+    // a relayer may leave the version empty, in which case this contract proposes its own
+    if !channel.version.is_empty() {
+        check_version(&channel.version)?;
+    }
+    if let Some(counterparty_version) = msg.counterparty_version() {
+        check_version(counterparty_version)?;
+    }
+    let port_id = &channel.counterparty_endpoint.port_id;
+    if !config.allowed_ports.is_empty() && !config.allowed_ports.contains(port_id) {
+        return Err(ContractError::UnauthorizedPort { port_id: port_id.clone() });
+    }
+    if !config.allowed_connections.is_empty() && !config.allowed_connections.contains(&channel.connection_id) {
+        return Err(ContractError::UnauthorizedConnection { connection_id: channel.connection_id.clone() });
+    }
 
+    Ok(Some(Ibc3ChannelOpenResponse { version: IBC_VERSION.to_string() }))
+}
+
+fn check_version(version: &str) -> Result<(), ContractError> {
+    if version != IBC_VERSION {
+        return Err(ContractError::InvalidIbcVersion { expected: IBC_VERSION.to_string(), actual: version.to_string() });
+    }
     Ok(())
 }
 
@@ -26,9 +56,14 @@ pub fn ibc_channel_connect(
     deps: DepsMut,
     env: Env,
     msg: IbcChannelConnectMsg,        
-) -> StdResult<IbcBasicResponse> {
+) -> Result<IbcBasicResponse, ContractError> {
+    // the counterparty answers the OpenInit with its version in OpenAck
+    if let Some(counterparty_version) = msg.counterparty_version() {
+        check_version(counterparty_version)?;
+    }
     let channel = msg.channel();
     let channel_id=&channel.endpoint.channel_id;
+    let timeout = default_timeout(deps.storage, &env)?;
     let mut state: State = STATE.load(deps.storage)?;
     state.node_number+=1;
     state.channel_ids.push(channel_id.to_string());
@@ -38,8 +73,8 @@ pub fn ibc_channel_connect(
     };
     let msg = IbcMsg::SendPacket {
         channel_id: channel_id.clone(),
-        data: to_json_binary(&packet)?,
-        timeout: get_timeout(&env)
+        data: packet_data(deps.storage, &packet)?,
+        timeout,
     };
 
     Ok(IbcBasicResponse::new()
@@ -53,12 +88,129 @@ pub fn ibc_channel_close(
     deps: DepsMut,
     env: Env,
     msg: IbcChannelCloseMsg,        
-) -> StdResult<IbcBasicResponse> {
+) -> Result<IbcBasicResponse, ContractError> {
     let channel = msg.channel();
     let channel_id = &channel.endpoint.channel_id;
+    let (msgs, aborted) = remove_peer(deps.storage, &env, channel_id)?;
     Ok(IbcBasicResponse::new()
+        .add_submessages(msgs)
         .add_attribute("action", "ibc_close")
-        .add_attribute("channel_id", channel_id))
+        .add_attribute("channel_id", channel_id)
+        .add_attribute("aborted", aborted.len().to_string()))
+}
+
+// forget the peer behind the channel altogether, after taking it out of the vote set
+pub fn remove_peer(
+    store: &mut dyn Storage,
+    env: &Env,
+    channel_id: &str,
+) -> Result<(Vec<SubMsg>, Vec<TxId>), ContractError> {
+    let chain_id = chain_of_channel(store, channel_id)?;
+    let result = deactivate_peer(store, env, channel_id, chain_id)?;
+    if let Some(chain_id) = chain_id {
+        CHANNELS.remove(store, chain_id);
+        CHAINS.remove(store, channel_id);
+        HIGHEST_ABORT.remove(store, chain_id);
+    }
+    Ok(result)
+}
+
+// drop the peer behind the channel from the vote set; in-flight transactions that still need its votes
+// can never reach quorum, so they are aborted, while the votes it already cast are taken back unless the transaction lists its participants
+pub fn deactivate_peer(
+    store: &mut dyn Storage,
+    env: &Env,
+    channel_id: &str,
+    chain_id: Option<u32>,
+) -> Result<(Vec<SubMsg>, Vec<TxId>), ContractError> {
+    let mut state = STATE.load(store)?;
+    if !state.channel_ids.iter().any(|id| id == channel_id) {
+        return Ok((vec![], vec![]));
+    }
+    state.channel_ids.retain(|id| id != channel_id);
+    state.node_number -= 1;
+    STATE.save(store, &state)?;
+    let has_voted = |store: &dyn Storage, voters: &Map<(TxId, u32), Timestamp>, tx_id: TxId| {
+        chain_id.is_some_and(|chain_id| voters.has(store, (tx_id, chain_id)))
+    };
+
+    let mut aborted: Vec<TxId> = Vec::new();
+    let mut queried: Vec<TxId> = Vec::new();
+    let queues = [&state.dirty_tx_queue, &state.prepare_tx_queue];
+    for queue in queues {
+        for tx_id in queue.iter() {
+            // a participant only hears from the coordinator, which collects the votes of everyone else,
+            // so only the loss of the coordinator can stall the transaction, as long as this chain has not voted to prepare it
+            if tx_role(store, *tx_id)? == Role::Participant {
+                let prepared = PREPARE_VOTERS.has(store, (*tx_id, state.chain_id));
+                if chain_id == Some(tx_id.origin) && !prepared {
+                    aborted.push(*tx_id);
+                }
+                continue;
+            }
+            // the vote that matters is the one of the phase the transaction is in;
+            // under the 3PC, a pre-committed transaction is committed by the phase timeout, whoever is left
+            let status = load_tx(store, *tx_id, env.block.time)?.status;
+            let (votes_map, voters_map) = match status {
+                TxStatus::Dirty => (&DIRTY_VOTES_MAP, &DIRTY_VOTERS),
+                TxStatus::PreCommitted => continue,
+                _ => (&PREPARE_VOTES_MAP, &PREPARE_VOTERS),
+            };
+            let voted = has_voted(store, voters_map, *tx_id);
+            // once this chain voted to prepare, a peer may already have committed, so it asks for the decision instead of aborting
+            let prepared = status == TxStatus::Prepared && PREPARE_VOTERS.has(store, (*tx_id, state.chain_id));
+            match tx_participants(store, *tx_id)? {
+                Some(participants) if !chain_id.is_some_and(|chain_id| participants.contains(&chain_id)) => {},
+                // the quorum of a transaction listing its participants does not shrink, so the peer is missing from every
+                // phase still to come; only a prepared transaction whose prepare votes are all in can still finish
+                Some(_) => {
+                    let complete = status == TxStatus::Prepared
+                        && PREPARE_VOTES_MAP.may_load(store, *tx_id)?.unwrap_or_default() >= tx_quorum(store, *tx_id)?;
+                    match (complete, prepared) {
+                        (true, _) => {},
+                        (false, true) => queried.push(*tx_id),
+                        (false, false) => aborted.push(*tx_id),
+                    }
+                },
+                None if voted => retract_vote(store, votes_map, voters_map, *tx_id, chain_id)?,
+                None if prepared => queried.push(*tx_id),
+                None => aborted.push(*tx_id),
+            }
+        }
+    }
+    // transactions that are not submitted locally yet keep waiting, but without the vote of the removed peer
+    let pending = txs()
+        .idx
+        .status
+        .prefix(TxStatus::Pending.as_str().to_string())
+        .keys(store, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<TxId>>>()?;
+    for tx_id in pending {
+        if has_voted(store, &DIRTY_VOTERS, tx_id) {
+            retract_vote(store, &DIRTY_VOTES_MAP, &DIRTY_VOTERS, tx_id, chain_id)?;
+        }
+    }
+
+    let mut msgs = abort_txs(store, env, &aborted)?;
+    for tx_id in queried {
+        msgs.append(&mut query_decision(store, env, tx_id)?);
+    }
+    Ok((msgs, aborted))
+}
+
+fn retract_vote(
+    store: &mut dyn Storage,
+    votes_map: &Map<TxId, u32>,
+    voters_map: &Map<(TxId, u32), Timestamp>,
+    tx_id: TxId,
+    chain_id: Option<u32>,
+) -> StdResult<()> {
+    if let Some(chain_id) = chain_id {
+        voters_map.remove(store, (tx_id, chain_id));
+        let votes = votes_map.may_load(store, tx_id)?.unwrap_or_default();
+        votes_map.save(store, tx_id, &votes.saturating_sub(1))?;
+    }
+    Ok(())
 }
 
 
@@ -68,35 +220,55 @@ pub fn ibc_packet_receive(
     env: Env,
     msg: IbcPacketReceiveMsg,        
 ) -> StdResult<IbcReceiveResponse> {
-    // let packet = msg.packet;
-    // do_ibc_packet_receive(deps, env, packet).or_else(
-    //     |e|{
-    //         let error = encode_ibc_error(format!("Failed to process IBC packet: {}", e));
-    //         Ok(IbcReceiveResponse::new()
-    //         .set_ack(error)
-    //         .add_attribute("action", "error"))
-    //     }
-    // )
-
-
-    (|| {
+    (|| -> Result<IbcReceiveResponse, ContractError> {
         let packet = msg.packet;
-        let dest_channel_id = packet.dest.channel_id;
-        let msg: Op = from_json(&packet.data)?;
         // which local channel did this packet come on
-        // let dest_channel_id = packet.dest.channel_id;
-        match msg{
-            Op::DirtySuccess{value} => {
+        let dest_channel_id = packet.dest.channel_id;
+        let data: PacketData = from_json(&packet.data)?;
+        merge_clock(deps.storage, data.lamport)?;
+        let digest = data.digest;
+        if let Some(tx_id) = data.op.tx_id() {
+            check_packet_digest(deps.storage, tx_id, digest.as_ref())?;
+        }
+        match data.op{
+            Op::DirtySuccess{value, tx} => {
                 // ...
-                handle_dirty_success(deps.storage, env, value)
+                handle_dirty_success(deps, env, dest_channel_id, value, tx, digest)
             },
             Op::Abortion{value} => {
                 // ...
-                handle_abortion(deps.storage, env, value)
+                handle_abortion(deps.storage, env, dest_channel_id, value)
             },
             Op::PrepareSuccess{value} => {
                 // ...
-                handle_prepare_success(deps.storage, env, value)
+                handle_prepare_success(deps.storage, env, dest_channel_id, value)
+            },
+            Op::Commit{value} => {
+                handle_commit(deps.storage, env, dest_channel_id, value)
+            },
+            Op::DecisionAck{value} => {
+                handle_decision_ack(deps.storage, env, dest_channel_id, value)
+            },
+            Op::QueryDecision{value} => {
+                handle_query_decision(deps.storage, env, dest_channel_id, value)
+            },
+            Op::Propose{value, tx} => {
+                handle_propose(deps, env, dest_channel_id, value, tx, digest)
+            },
+            Op::Prepare{value} => {
+                handle_prepare(deps.storage, env, dest_channel_id, value)
+            },
+            Op::PreCommit{value} => {
+                handle_pre_commit(deps.storage, env, dest_channel_id, value)
+            },
+            Op::SagaStep{value, tx, step} => {
+                handle_saga_step(deps, env, dest_channel_id, value, tx, step, digest)
+            },
+            Op::Compensate{value, step} => {
+                handle_compensate(deps.storage, env, dest_channel_id, value, step)
+            },
+            Op::StepResult{value, step, compensation, error} => {
+                handle_step_result(deps.storage, env, dest_channel_id, value, step, compensation, error)
             },
             Op::WhoAmI { chain_id } => {
                 // ...
@@ -123,71 +295,127 @@ pub fn ibc_packet_ack(
     deps: DepsMut,
     env: Env,
     msg: IbcPacketAckMsg,        
-) -> StdResult<IbcBasicResponse> {
-    Ok(IbcBasicResponse::default())
+) -> Result<IbcBasicResponse, ContractError> {
+    let packet = match from_json::<PacketData>(&msg.original_packet.data) {
+        Ok(data) => data.op,
+        Err(_) => return Ok(legacy_packet("ibc_packet_ack")),
+    };
+    let tx_id = match packet.tx_id() {
+        Some(tx_id) => tx_id,
+        // the handshake acknowledgement carries nothing to act on
+        None => return Ok(IbcBasicResponse::new().add_attribute("action", "ibc_packet_ack")),
+    };
+    let ack: AcknowledgementMsg<MsgQueueResponse> = from_json(&msg.acknowledgement.data)?;
+    let response = IbcBasicResponse::new()
+        .add_attribute("action", "ibc_packet_ack")
+        .add_attribute("tx_id", tx_id.to_string());
+    match ack {
+        ContractResult::Ok(confirmation) => {
+            // the confirmations of a pruned transaction are not kept
+            let known = TX_PAYLOADS.has(deps.storage, tx_id);
+            if let Some(chain_id) = chain_of_channel(deps.storage, &msg.original_packet.src.channel_id)?.filter(|_| known) {
+                CONFIRMATIONS.save(deps.storage, (tx_id, packet.kind(), chain_id), &confirmation.status)?;
+            }
+            let mut msgs: Vec<SubMsg> = Vec::new();
+            if let Op::QueryDecision { .. } = packet {
+                msgs = adopt_decision(deps.storage, &env, tx_id, confirmation.status)?;
+            }
+            Ok(response
+                .add_submessages(msgs)
+                .add_attribute("success", "true"))
+        },
+        ContractResult::Err(error) => {
+            // the peer rejected the message, so the transaction cannot complete there,
+            // while a saga step that was refused counts as failed
+            let mut msgs: Vec<SubMsg> = Vec::new();
+            match packet {
+                Op::SagaStep { step, .. } => msgs = on_step_result(deps.storage, &env, tx_id, step, false, Some(error.clone()))?,
+                Op::Compensate { step, .. } => msgs = on_step_result(deps.storage, &env, tx_id, step, true, Some(error.clone()))?,
+                // a refused query is left to the next Resolve
+                Op::StepResult { .. } | Op::QueryDecision { .. } => {},
+                _ => msgs = fail_tx(deps.storage, &env, tx_id)?,
+            }
+            Ok(response
+                .add_submessages(msgs)
+                .add_attribute("success", "false")
+                .add_attribute("error", error))
+        },
+    }
 }
 
 #[entry_point]
 pub fn ibc_packet_timeout(
-    _deps: DepsMut,
-    _env: Env,
-    _msg: IbcPacketTimeoutMsg,
-) -> StdResult<IbcBasicResponse> {
-    Ok(IbcBasicResponse::new().add_attribute("action", "ibc_packet_timeout"))
-}
-
-
-
-
-fn do_ibc_packet_receive(
     deps: DepsMut,
     env: Env,
-    packet: IbcPacket,
-) -> StdResult<IbcReceiveResponse> {
-
-    let msg: Op = from_slice(&packet.data)?;
-    let dest_channel_id = packet.dest.channel_id;
-    match msg{
-        Op::DirtySuccess{value} => {
-            // ...
-            handle_dirty_success(deps.storage, env, value)
-        },
-        Op::Abortion{value} => {
-            // ...
-            handle_abortion(deps.storage, env, value)
-        },
-        Op::PrepareSuccess{value} => {
-            // ...
-            handle_prepare_success(deps.storage, env, value)
-        },
-        Op::WhoAmI { chain_id } => {
-            // ...
-            receive_who_am_i(deps, dest_channel_id,chain_id)
-           
-        }
-
-   
+    msg: IbcPacketTimeoutMsg,
+) -> Result<IbcBasicResponse, ContractError> {
+    // a vote that never reached a peer means the transaction can never reach quorum there
+    let packet = match from_json::<PacketData>(&msg.packet.data) {
+        Ok(data) => data.op,
+        Err(_) => return Ok(legacy_packet("ibc_packet_timeout")),
+    };
+    let tx_id = match packet {
+        Op::DirtySuccess { value, .. }
+        | Op::PrepareSuccess { value }
+        | Op::Propose { value, .. }
+        | Op::Prepare { value } => Some(value),
+        Op::Abortion { .. }
+        | Op::Commit { .. }
+        | Op::DecisionAck { .. }
+        | Op::QueryDecision { .. }
+        | Op::PreCommit { .. }
+        | Op::SagaStep { .. }
+        | Op::Compensate { .. }
+        | Op::StepResult { .. }
+        | Op::WhoAmI { .. } => None,
+    };
+    let mut msgs: Vec<SubMsg> = Vec::new();
+    if let Some(tx_id) = tx_id {
+        msgs = fail_tx(deps.storage, &env, tx_id)?;
+    }
+    // a saga step that never reached its chain did not run there
+    let error = "packet timed out".to_string();
+    match packet {
+        Op::SagaStep { value, step, .. } => msgs = on_step_result(deps.storage, &env, value, step, false, Some(error))?,
+        Op::Compensate { value, step } => msgs = on_step_result(deps.storage, &env, value, step, true, Some(error))?,
+        _ => {},
+    }
+    let mut response = IbcBasicResponse::new()
+        .add_submessages(msgs)
+        .add_attribute("action", "ibc_packet_timeout");
+    if let Some(tx_id) = packet.tx_id() {
+        response = response.add_attribute("tx_id", tx_id.to_string());
     }
+    Ok(response)
 }
 
+
+
+
 fn receive_who_am_i(
     deps: DepsMut,
     channel_id: String,
     chain_id: u32,
-) -> StdResult<IbcReceiveResponse> {
-   
+) -> Result<IbcReceiveResponse, ContractError> {
+    if chain_id == LEGACY_ORIGIN {
+        return Err(ContractError::ReservedChainId { chain_id });
+    }
 
-    let action = |_| -> StdResult<String> { Ok(channel_id.to_string()) };
-    CHANNELS.update(deps.storage, chain_id, action)?;
+    // a chain speaks on one channel only, so no channel can take over the identity of another chain
+    if let Some(bound) = CHANNELS.may_load(deps.storage, chain_id)?.filter(|bound| *bound != channel_id) {
+        return Err(ContractError::ChainBound { chain_id, channel_id: bound });
+    }
+    if let Some(bound) = CHAINS.may_load(deps.storage, &channel_id)?.filter(|bound| *bound != chain_id) {
+        return Err(ContractError::ChannelBound { channel_id, chain_id: bound });
+    }
+    CHANNELS.save(deps.storage, chain_id, &channel_id)?;
+    CHAINS.save(deps.storage, &channel_id, &chain_id)?;
 
-    // initialize the highest_request of that chain
-    // let action = |_| -> StdResult<u32> { Ok(0) };
-    // HIGHEST_REQ.update(deps.storage, chain_id, action)?;
     // initialize the highest_request of that chain
     HIGHEST_ABORT.save(deps.storage, chain_id, &-1)?;
 
     let response = WhoAmIResponse {};
-    let acknowledgement = to_binary(&AcknowledgementMsg::Ok(response))?;
+    let acknowledgement = to_json_binary(&AcknowledgementMsg::Ok(response))?;
     // and we are golden
     Ok(IbcReceiveResponse::new()
         .set_ack(acknowledgement)
@@ -197,181 +425,651 @@ fn receive_who_am_i(
 
 
 fn handle_dirty_success(
-    store: & mut dyn Storage,
+    mut deps: DepsMut,
     env: Env,
-    value: u32,
-) -> StdResult<IbcReceiveResponse> {
+    channel_id: String,
+    value: TxId,
+    payload: Option<Transaction>,
+    digest: Option<HexBinary>,
+) -> Result<IbcReceiveResponse, ContractError> {
     // ...
-    // check if dirty votes map for the key value is empty
-
-    let mut state = STATE.load(store)?;
-    state.dirty_votes+=1;
-    STATE.save(store, &state)?;
+    // count the vote for this transaction only, votes of other in-flight transactions are kept apart
+    let voter = voter_of_channel(deps.storage, channel_id)?;
+    check_participant(deps.storage, value, voter)?;
+    // the vote may arrive before the announcement of the origin, in which case the transaction stays pending
+    let tx = load_tx(deps.storage, value, env.block.time)?;
+    let join = check_announcement(deps.storage, &tx, value, voter, payload, digest.as_ref())?;
+    txs().save(deps.storage, value, &tx)?;
+    // a replayed vote is acknowledged but not counted again, nor is a late vote for a settled transaction
+    let counted = !tx.status.is_final() && add_vote(deps.storage, &DIRTY_VOTES_MAP, &DIRTY_VOTERS, value, voter, env.block.time)?;
     
-    let msgs = check_dirty(store, env.clone(), value, get_timeout(&env)).unwrap();
-    let acknowledgement = to_binary(&AcknowledgementMsg::Ok(MsgQueueResponse { }))?; 
+    let mut msgs: Vec<SubMsg> = Vec::new();
+    if let Some(payload) = join {
+        // joining counts the local vote, and checks the dirty votes once it is in
+        msgs = join_tx(deps.branch(), &env, value, payload, digest, None)?.0;
+    } else if counted {
+        msgs = check_dirty(deps.storage, env.clone(), value)?;
+    }
+    let acknowledgement = ack_tx(deps.storage, value)?;
     Ok(IbcReceiveResponse::new()
         .set_ack(acknowledgement)
-        .add_messages(msgs)
-        .add_attribute("action", "handle_dirty_success"))
+        .add_submessages(msgs)
+        .add_attribute("action", "handle_dirty_success")
+        .add_attribute("tx_id", value.to_string())
+        .add_attribute("duplicate", (!counted).to_string()))
 }
 
 
-fn handle_prepare_success(
+// coordinator mode: the origin announces the transaction without voting yet, and every participant joins it
+// and votes back to the origin only
+fn handle_propose(
+    mut deps: DepsMut,
+    env: Env,
+    channel_id: String,
+    value: TxId,
+    payload: Transaction,
+    digest: Option<HexBinary>,
+) -> Result<IbcReceiveResponse, ContractError> {
+    let origin = voter_of_channel(deps.storage, channel_id)?;
+    let tx = load_tx(deps.storage, value, env.block.time)?;
+    let join = check_announcement(deps.storage, &tx, value, origin, Some(payload), digest.as_ref())?;
+    txs().save(deps.storage, value, &tx)?;
+    let mut msgs: Vec<SubMsg> = Vec::new();
+    if let Some(payload) = join {
+        msgs = join_tx(deps.branch(), &env, value, payload, digest, None)?.0;
+    }
+
+    let acknowledgement = ack_tx(deps.storage, value)?;
+    Ok(IbcReceiveResponse::new()
+        .set_ack(acknowledgement)
+        .add_submessages(msgs)
+        .add_attribute("action", "handle_propose")
+        .add_attribute("tx_id", value.to_string()))
+}
+
+
+// coordinator mode: every participant voted for the dirty phase, which the origin tells with its own dirty vote
+fn handle_prepare(
     store: &mut dyn Storage,
     env: Env,
-    value: u32,
-) -> StdResult<IbcReceiveResponse> {
-    // ...
+    channel_id: String,
+    value: TxId,
+) -> Result<IbcReceiveResponse, ContractError> {
+    let voter = voter_of_channel(store, channel_id)?;
+    if voter != value.origin {
+        return Err(ContractError::NotOrigin { tx_id: value, chain_id: voter });
+    }
+    let tx = load_tx(store, value, env.block.time)?;
+    txs().save(store, value, &tx)?;
+    let counted = add_vote(store, &DIRTY_VOTES_MAP, &DIRTY_VOTERS, value, voter, env.block.time)?;
+    let mut msgs: Vec<SubMsg> = Vec::new();
+    if counted {
+        msgs = check_dirty(store, env.clone(), value)?;
+    }
 
-    
-    let mut state = STATE.load(store)?;
-    state.prepare_votes+=1;
-    STATE.save(store, &state)?;
-    let mut msgs = handle_deadlock(store, env.clone(), value).unwrap();
+    let acknowledgement = ack_tx(store, value)?;
+    Ok(IbcReceiveResponse::new()
+        .set_ack(acknowledgement)
+        .add_submessages(msgs)
+        .add_attribute("action", "handle_prepare")
+        .add_attribute("tx_id", value.to_string())
+        .add_attribute("duplicate", (!counted).to_string()))
+}
 
 
+// the payload to join if the message announces the transaction; only the origin announces a transaction, only once,
+// and only to the chains taking part in it
+fn check_announcement(
+    store: &dyn Storage,
+    tx: &TxInfo,
+    tx_id: TxId,
+    sender: u32,
+    payload: Option<Transaction>,
+    digest: Option<&HexBinary>,
+) -> Result<Option<Transaction>, ContractError> {
+    let payload = match payload {
+        Some(_) if sender != tx_id.origin => return Err(ContractError::NotOrigin { tx_id, chain_id: sender }),
+        // the transaction was already aborted here, there is nothing left to join
+        Some(_) if tx.status == TxStatus::Aborted => return Ok(None),
+        Some(_) if tx.status != TxStatus::Pending || TX_PAYLOADS.has(store, tx_id) => return Err(ContractError::TxExists { tx_id }),
+        Some(payload) => payload,
+        None => return Ok(None),
+    };
+    let chain_id = STATE.load(store)?.chain_id;
+    if !payload.participants.is_empty() && !payload.participants.contains(&chain_id) {
+        return Err(ContractError::NotParticipant { tx_id, chain_id });
+    }
+    // the announced payload has to hash to the announced digest, which is required if this chain only takes content addressed transactions
+    let content_addressed = CONFIG.load(store)?.content_addressed;
+    match digest {
+        Some(digest) if *digest != tx_digest(tx_id, &payload)? => return Err(ContractError::InvalidDigest { tx_id }),
+        None if content_addressed => return Err(ContractError::InvalidDigest { tx_id }),
+        _ => {},
+    }
+    Ok(Some(payload))
+}
 
-    let mut state = STATE.load(store)?;
-    // if the number of votes is equal to the number of nodes, remove the transaction from the prepare queue and add it to the commit queue
-    // moreover, if a subsequent transaction exists in the prepare_tx_queue, add the votes by 1 and send the prepare success message to other blockchains.
-    if state.prepare_votes == state.node_number{
-        state.prepare_tx_queue.remove(0);
-        state.commit_tx_queue.push(value);
-        // if !state.prepare_tx_queue.is_empty(){
-        //     let value = state.prepare_tx_queue[0].tx_id;
-        //     if PREPARE_VOTES_MAP.may_load(store, value)?.is_none(){
-        //         PREPARE_VOTES_MAP.save(store, value, &1)?;
-        //     }else{
-        //         let votes = PREPARE_VOTES_MAP.load(store, value)?;
-        //         PREPARE_VOTES_MAP.save(store, value, &(votes+1))?;
-        //     }
-        //     for channel_id in state.channel_ids.iter() {
-        //         let packet = Op::PrepareSuccess { value };
-        //         let ibc_msg = IbcMsg::SendPacket {
-        //             channel_id: channel_id.clone(),
-        //             data: to_binary(&packet).unwrap(),
-        //             timeout: get_timeout(&env),
-        //         };
-        //         // 将新创建的消息添加到msgs向量中
-        //         msgs.push(ibc_msg);
-        //     }
-        // }
-
-        state.committed=true;
-        state.end_time=Some(env.block.time);
-        STATE.save(store, &state)?;
-
-    }
-
-
-
-    let acknowledgement = to_binary(&AcknowledgementMsg::Ok(MsgQueueResponse { }))?; 
+
+// every packet about a transaction carries the digest this chain knows it by, and a content addressed chain only takes digested transactions
+fn check_packet_digest(
+    store: &dyn Storage,
+    tx_id: TxId,
+    digest: Option<&HexBinary>,
+) -> Result<(), ContractError> {
+    let known = txs().may_load(store, tx_id)?.and_then(|tx| tx.digest);
+    let valid = match (known, digest) {
+        (Some(known), digest) => digest == Some(&known),
+        (None, None) => !CONFIG.load(store)?.content_addressed,
+        (None, Some(_)) => true,
+    };
+    if !valid {
+        return Err(ContractError::InvalidDigest { tx_id });
+    }
+    Ok(())
+}
+
+
+// saga mode: the origin asks this chain to run a step, which announces the transaction along with the first one;
+// the application may refuse the transaction when it is announced, which fails the step
+#[allow(clippy::too_many_arguments)]
+fn handle_saga_step(
+    deps: DepsMut,
+    env: Env,
+    channel_id: String,
+    value: TxId,
+    payload: Transaction,
+    step: u32,
+    digest: Option<HexBinary>,
+) -> Result<IbcReceiveResponse, ContractError> {
+    let origin = voter_of_channel(deps.storage, channel_id)?;
+    if origin != value.origin {
+        return Err(ContractError::NotOrigin { tx_id: value, chain_id: origin });
+    }
+    let tx = load_tx(deps.storage, value, env.block.time)?;
+    let join = match TX_PAYLOADS.has(deps.storage, value) {
+        true => None,
+        false => check_announcement(deps.storage, &tx, value, origin, Some(payload), digest.as_ref())?,
+    };
+    txs().save(deps.storage, value, &tx)?;
+    if let Some(payload) = join {
+        let mut tx = transition_tx(deps.storage, value, TxStatus::Dirty, env.block.time)?;
+        if let Some(digest) = &digest {
+            TX_DIGESTS.save(deps.storage, digest.as_slice(), &value)?;
+        }
+        tx.digest = digest;
+        tx.participants = Some(payload.participants.clone());
+        txs().save(deps.storage, value, &tx)?;
+        TX_PAYLOADS.save(deps.storage, value, &payload)?;
+        if let Some(reason) = check_can_prepare(deps.as_ref(), &payload)? {
+            let mut tx = transition_tx(deps.storage, value, TxStatus::Aborted, env.block.time)?;
+            tx.error = Some(reason.clone());
+            txs().save(deps.storage, value, &tx)?;
+            prune_aborted(deps.storage, value)?;
+            return Err(ContractError::Refused { tx_id: value, reason });
+        }
+    }
+    let msg = receive_step(deps.storage, &env, value, step, false)?;
+
+    let acknowledgement = ack_tx(deps.storage, value)?;
     Ok(IbcReceiveResponse::new()
         .set_ack(acknowledgement)
-        .add_messages(msgs)
-        .add_attribute("action", "handle_prepare_success"))
+        .add_submessage(msg)
+        .add_attribute("action", "handle_saga_step")
+        .add_attribute("tx_id", value.to_string())
+        .add_attribute("step", step.to_string()))
 }
 
 
+// saga mode: the origin asks this chain to undo a step it ran
+fn handle_compensate(
+    store: &mut dyn Storage,
+    env: Env,
+    channel_id: String,
+    value: TxId,
+    step: u32,
+) -> Result<IbcReceiveResponse, ContractError> {
+    let origin = voter_of_channel(store, channel_id)?;
+    if origin != value.origin {
+        return Err(ContractError::NotOrigin { tx_id: value, chain_id: origin });
+    }
+    let msg = receive_step(store, &env, value, step, true)?;
 
+    let acknowledgement = ack_tx(store, value)?;
+    Ok(IbcReceiveResponse::new()
+        .set_ack(acknowledgement)
+        .add_submessage(msg)
+        .add_attribute("action", "handle_compensate")
+        .add_attribute("tx_id", value.to_string())
+        .add_attribute("step", step.to_string()))
+}
+
+
+// saga mode: the chain running a step of a transaction that originates here reports how it went
+#[allow(clippy::too_many_arguments)]
+fn handle_step_result(
+    store: &mut dyn Storage,
+    env: Env,
+    channel_id: String,
+    value: TxId,
+    step: u32,
+    compensation: bool,
+    error: Option<String>,
+) -> Result<IbcReceiveResponse, ContractError> {
+    let voter = voter_of_channel(store, channel_id)?;
+    let info = SAGA_STEPS.may_load(store, (value, step))?
+        .ok_or(ContractError::InvalidStep { tx_id: value, step })?;
+    if info.chain_id != voter {
+        return Err(ContractError::NotParticipant { tx_id: value, chain_id: voter });
+    }
+    let msgs = on_step_result(store, &env, value, step, compensation, error)?;
 
+    let acknowledgement = ack_tx(store, value)?;
+    Ok(IbcReceiveResponse::new()
+        .set_ack(acknowledgement)
+        .add_submessages(msgs)
+        .add_attribute("action", "handle_step_result")
+        .add_attribute("tx_id", value.to_string())
+        .add_attribute("step", step.to_string()))
+}
 
 
-fn handle_abortion(
+// 3PC: a chain that acquired all prepare votes; a participant pre-commits as soon as its coordinator does
+fn handle_pre_commit(
     store: &mut dyn Storage,
     env: Env,
-    value: u32,
-) -> StdResult<IbcReceiveResponse> {
-    // ...
-    let mut state = STATE.load(store)?;
-    // remove_all_pending_elements(&mut state.prepare_tx_queue, value);
-    // let mut msgs :Vec<IbcMsg> = Vec::new();
-    // if !removed_elements_prepare.is_empty(){
-    //     for tx_id in removed_elements_prepare{
-    //         msgs.append(& mut upon_failure(store, env.block.time.clone(), tx_id, get_timeout(&env)).unwrap());
-    //     }
-    // }
-    // remove_all_pending_elements(&mut state.dirty_tx_queue, value);
-    STATE.save(store, &state)?;
+    channel_id: String,
+    value: TxId,
+) -> Result<IbcReceiveResponse, ContractError> {
+    let voter = voter_of_channel(store, channel_id)?;
+    check_participant(store, value, voter)?;
+    let tx = load_tx(store, value, env.block.time)?;
+    txs().save(store, value, &tx)?;
+    let counted = !tx.status.is_final() && add_vote(store, &PRE_COMMIT_VOTES_MAP, &PRE_COMMIT_VOTERS, value, voter, env.block.time)?;
+    let mut msgs: Vec<SubMsg> = Vec::new();
+    if counted {
+        msgs = match (tx.status, tx_role(store, value)?) {
+            (TxStatus::Prepared, Role::Participant) if voter == value.origin => pre_commit(store, &env, value)?,
+            _ => check_pre_commit(store, &env, value)?,
+        };
+    }
 
-    let acknowledgement = to_binary(&AcknowledgementMsg::Ok(MsgQueueResponse { }))?; 
+    let acknowledgement = ack_tx(store, value)?;
     Ok(IbcReceiveResponse::new()
         .set_ack(acknowledgement)
-        // .add_messages(msgs)
-        .add_attribute("action", "handle_failure"))
+        .add_submessages(msgs)
+        .add_attribute("action", "handle_pre_commit")
+        .add_attribute("tx_id", value.to_string())
+        .add_attribute("duplicate", (!counted).to_string()))
 }
 
 
+fn handle_prepare_success(
+    store: &mut dyn Storage,
+    env: Env,
+    channel_id: String,
+    value: TxId,
+) -> Result<IbcReceiveResponse, ContractError> {
+    // ...
 
+    let voter = voter_of_channel(store, channel_id)?;
+    check_participant(store, value, voter)?;
+    let tx = load_tx(store, value, env.block.time)?;
+    txs().save(store, value, &tx)?;
+    let counted = !tx.status.is_final() && add_vote(store, &PREPARE_VOTES_MAP, &PREPARE_VOTERS, value, voter, env.block.time)?;
+    let mut msgs: Vec<SubMsg> = Vec::new();
+    if counted {
+        msgs = check_prepare(store, &env, value)?;
+    }
 
-fn encode_ibc_error(msg: impl Into<String>) -> Binary {
-    // this cannot error, unwrap to keep the interface simple
-    to_binary(&ContractResult::<()>::Err(msg.into())).unwrap()
+    let acknowledgement = ack_tx(store, value)?;
+    Ok(IbcReceiveResponse::new()
+        .set_ack(acknowledgement)
+        .add_submessages(msgs)
+        .add_attribute("action", "handle_prepare_success")
+        .add_attribute("tx_id", value.to_string())
+        .add_attribute("duplicate", (!counted).to_string()))
 }
 
 
-fn handle_deadlock(
+
+
+
+
+fn handle_abortion(
     store: &mut dyn Storage,
     env: Env,
-    tx_id: u32,
-) -> Result<Vec<IbcMsg>, ContractError>{
+    channel_id: String,
+    value: TxId,
+) -> Result<IbcReceiveResponse, ContractError> {
     // ...
-    let mut state = STATE.load(store)?;
-    // if the transaction is not at the top of the prepare queue, remove it and abort all subsequent transactions in the prepare queue, including it, since it indicates a deadlock
-    // however, if the transaction even does not exist in the prepare queue, needs to check whether it is the top element of the dirty queue, if so, do not see it as a deadlock
-    // if the transaction is not at the top of the dirty queue, remove it since even though it is promoted to the prepare queue, it is not the top element 
-    let is_top_prepare = check_queue_top(state.prepare_tx_queue.clone(), tx_id);
-    let is_top_dirty=check_queue_top(state.dirty_tx_queue.clone(), tx_id);
-    let mut msgs: Vec<IbcMsg> = Vec::new();
-    if !is_top_prepare && !is_top_dirty{
-        let removed_elements = remove_all_pending_elements(&mut state.prepare_tx_queue, tx_id);
-        STATE.save(store, &state)?;
-        for tx_id in removed_elements{
-            msgs = upon_failure(store, env.block.time.clone(), tx_id, get_timeout(&env))?;
+    let voter = voter_of_channel(store, channel_id)?;
+    check_participant(store, value, voter)?;
+    // every chain broadcasts the abortion, so the same transaction may be aborted more than once
+    let mut msgs: Vec<SubMsg> = Vec::new();
+    if load_tx(store, value, env.block.time)?.status != TxStatus::Aborted {
+        // a participant only tells the coordinator, which passes the abortion on to everyone else
+        if tx_role(store, value)? == Role::Coordinator {
+            msgs = upon_failure(store, &env, value)?;
+        } else {
+            msgs = abort_locally(store, &env, value)?;
         }
-        // check if dirty_tx_queue is empty
-        if !state.dirty_tx_queue.is_empty(){
-            let tx_id = state.dirty_tx_queue[0];
-            let removed_elements = remove_all_pending_elements(& mut state.prepare_tx_queue, tx_id);
-            for tx_id in removed_elements{
-                msgs.append(& mut upon_failure(store, env.block.time.clone(), tx_id, get_timeout(&env))?);
-            }   
+        msgs.append(&mut remove_from_queues(store, &env, value)?);
+    }
+
+    let acknowledgement = ack_tx(store, value)?;
+    Ok(IbcReceiveResponse::new()
+        .set_ack(acknowledgement)
+        .add_submessages(msgs)
+        .add_attribute("action", "handle_failure")
+        .add_attribute("tx_id", value.to_string()))
+}
+
+
+
+
+fn handle_commit(
+    store: &mut dyn Storage,
+    env: Env,
+    channel_id: String,
+    value: TxId,
+) -> Result<IbcReceiveResponse, ContractError> {
+    let voter = voter_of_channel(store, channel_id.clone())?;
+    check_participant(store, value, voter)?;
+    // a participant only takes the decision of the coordinator
+    if tx_role(store, value)? == Role::Participant && voter != value.origin {
+        return Err(ContractError::NotOrigin { tx_id: value, chain_id: voter });
+    }
+    let timeout = tx_timeout(store, &env, value)?;
+    let tx = load_tx(store, value, env.block.time)?;
+    let mut msgs: Vec<SubMsg> = Vec::new();
+    // a chain that missed some of the prepare votes learns the decision from its peers
+    if tx.status != TxStatus::Committed {
+        if !tx.status.can_transition_to(TxStatus::Committed) {
+            return Err(ContractError::InvalidTransition { tx_id: value, from: tx.status, to: TxStatus::Committed });
         }
-        
+        msgs = commit_tx(store, &env, value)?;
+    }
+    let packet = Op::DecisionAck { value };
+    msgs.push(SubMsg::new(IbcMsg::SendPacket {
+        channel_id,
+        data: packet_data(store, &packet)?,
+        timeout,
+    }));
+    // a chain that learned the decision has acknowledged it with this packet, and nobody waits for it anymore
+    if tx.status != TxStatus::Committed {
+        prune_tx(store, value)?;
+    }
+
+    let acknowledgement = ack_tx(store, value)?;
+    Ok(IbcReceiveResponse::new()
+        .set_ack(acknowledgement)
+        .add_submessages(msgs)
+        .add_attribute("action", "handle_commit")
+        .add_attribute("tx_id", value.to_string()))
+}
+
+
+// cooperative termination: a peer that knows the outcome tells it, and a peer that has not voted for the prepare phase yet
+// can still abort on its own, which settles the transaction for everyone; a prepared peer is as uncertain as the asker
+fn handle_query_decision(
+    store: &mut dyn Storage,
+    env: Env,
+    channel_id: String,
+    value: TxId,
+) -> Result<IbcReceiveResponse, ContractError> {
+    let voter = voter_of_channel(store, channel_id)?;
+    check_participant(store, value, voter)?;
+    let tx = load_tx(store, value, env.block.time)?;
+    let mut msgs: Vec<SubMsg> = Vec::new();
+    // a saga in progress is settled by its steps alone
+    let saga = TX_PAYLOADS.may_load(store, value)?.is_some_and(|payload| payload.saga);
+    if matches!(tx.status, TxStatus::Pending | TxStatus::Dirty) && !saga {
+        msgs = abort_txs(store, &env, &[value])?;
     }
+
+    let acknowledgement = ack_tx(store, value)?;
+    Ok(IbcReceiveResponse::new()
+        .set_ack(acknowledgement)
+        .add_submessages(msgs)
+        .add_attribute("action", "handle_query_decision")
+        .add_attribute("tx_id", value.to_string()))
+}
+
+
+// packets sent before the upgrade have the layout of 0.0.x, whose transactions were all settled by the migration,
+// so their acknowledgements and timeouts are only cleared
+fn legacy_packet(action: &str) -> IbcBasicResponse {
+    IbcBasicResponse::new()
+        .add_attribute("action", action)
+        .add_attribute("packet", "undecodable")
+}
+
+// a message about the transaction did not get through: abort it, unless this chain already voted to prepare it,
+// in which case a peer may have committed and only the decision settles it, so the peers are asked about it;
+// under the 3PC, a pre-committed transaction is committed by the phase timeout
+fn fail_tx(
+    store: &mut dyn Storage,
+    env: &Env,
+    tx_id: TxId,
+) -> Result<Vec<SubMsg>, ContractError> {
+    let status = load_tx(store, tx_id, env.block.time)?.status;
+    if status.is_final() || status == TxStatus::PreCommitted {
+        return Ok(vec![]);
+    }
+    if PREPARE_VOTERS.has(store, (tx_id, STATE.load(store)?.chain_id)) {
+        return query_decision(store, env, tx_id);
+    }
+    abort_txs(store, env, &[tx_id])
+}
+
+// ask every peer of the transaction about its outcome, the answers come back in the acknowledgements
+pub fn query_decision(
+    store: &mut dyn Storage,
+    env: &Env,
+    tx_id: TxId,
+) -> Result<Vec<SubMsg>, ContractError> {
+    let timeout = tx_timeout(store, env, tx_id)?;
+    let data = packet_data(store, &Op::QueryDecision { value: tx_id })?;
+    let msgs = tx_channels(store, tx_id)?
+        .into_iter()
+        .map(|channel_id| SubMsg::new(IbcMsg::SendPacket {
+            channel_id,
+            data: data.clone(),
+            timeout: timeout.clone(),
+        }))
+        .collect();
     Ok(msgs)
+}
+
+// follow the outcome a peer reported for a transaction this chain is uncertain about
+fn adopt_decision(
+    store: &mut dyn Storage,
+    env: &Env,
+    tx_id: TxId,
+    outcome: TxStatus,
+) -> Result<Vec<SubMsg>, ContractError> {
+    let status = load_tx(store, tx_id, env.block.time)?.status;
+    match outcome {
+        TxStatus::Committed if status.can_transition_to(TxStatus::Committed) => {
+            let msgs = commit_tx(store, env, tx_id)?;
+            prune_tx(store, tx_id)?;
+            Ok(msgs)
+        },
+        TxStatus::Aborted if !status.is_final() => abort_txs(store, env, &[tx_id]),
+        _ => Ok(vec![]),
+    }
+}
+
+
+fn handle_decision_ack(
+    store: &mut dyn Storage,
+    env: Env,
+    channel_id: String,
+    value: TxId,
+) -> Result<IbcReceiveResponse, ContractError> {
+    // only a chain that was told about the commit of this chain can acknowledge it
+    if txs().load(store, value)?.status != TxStatus::Committed {
+        return Err(ContractError::NotCommitted { tx_id: value });
+    }
+    let chain_id = chain_of_channel(store, &channel_id)?
+        .ok_or(ContractError::UnknownChannel { channel_id: channel_id.clone() })?;
+    if !tx_channels(store, value)?.contains(&channel_id) {
+        return Err(ContractError::NotParticipant { tx_id: value, chain_id });
+    }
+    // the transaction was pruned already, a late ack has nothing left to count
+    if !TX_PAYLOADS.has(store, value) {
+        let acknowledgement = ack_tx(store, value)?;
+        return Ok(IbcReceiveResponse::new()
+            .set_ack(acknowledgement)
+            .add_attribute("action", "handle_decision_ack")
+            .add_attribute("tx_id", value.to_string())
+            .add_attribute("finished", "true"));
+    }
+    DECISION_ACKS.save(store, (value, chain_id), &env.block.time)?;
+    let acks = DECISION_ACKS
+        .prefix(value)
+        .keys(store, None, None, Order::Ascending)
+        .count();
+    // no peer can ask about the transaction anymore, so only its record is kept
+    let finished = acks >= tx_channels(store, value)?.len();
+    if finished {
+        prune_tx(store, value)?;
+    }
+
+    let acknowledgement = ack_tx(store, value)?;
+    Ok(IbcReceiveResponse::new()
+        .set_ack(acknowledgement)
+        .add_attribute("action", "handle_decision_ack")
+        .add_attribute("tx_id", value.to_string())
+        .add_attribute("finished", finished.to_string()))
+}
+
+
+fn prune_tx(
+    store: &mut dyn Storage,
+    tx_id: TxId,
+) -> StdResult<()> {
+    TX_PAYLOADS.remove(store, tx_id);
+    for votes in [&DIRTY_VOTES_MAP, &PREPARE_VOTES_MAP, &PRE_COMMIT_VOTES_MAP] {
+        votes.remove(store, tx_id);
+    }
+    for voters in [&DIRTY_VOTERS, &PREPARE_VOTERS, &PRE_COMMIT_VOTERS, &DECISION_ACKS] {
+        let chain_ids = voters
+            .prefix(tx_id)
+            .keys(store, None, None, Order::Ascending)
+            .collect::<StdResult<Vec<u32>>>()?;
+        for chain_id in chain_ids {
+            voters.remove(store, (tx_id, chain_id));
+        }
+    }
+    let confirmations = CONFIRMATIONS
+        .sub_prefix(tx_id)
+        .keys(store, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<(String, u32)>>>()?;
+    for (phase, chain_id) in confirmations {
+        CONFIRMATIONS.remove(store, (tx_id, &phase, chain_id));
+    }
+    Ok(())
+}
+
+
+// an aborted transaction is dropped once nothing runs for it anymore, since its compensation and the steps
+// of a saga still need its payload; a step that is done here is undone later, so it keeps the payload too
+pub fn prune_aborted(
+    store: &mut dyn Storage,
+    tx_id: TxId,
+) -> StdResult<()> {
+    if txs().load(store, tx_id)?.status != TxStatus::Aborted {
+        return Ok(());
+    }
+    let pending = |replies: &Map<u64, TxId>| -> StdResult<bool> {
+        for item in replies.range(store, None, None, Order::Ascending) {
+            if item?.1 == tx_id {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    };
+    if pending(&PENDING_REPLIES)? || pending(&PENDING_COMPENSATIONS)? {
+        return Ok(());
+    }
+    for item in SAGA_STEPS.prefix(tx_id).range(store, None, None, Order::Ascending) {
+        if matches!(item?.1.status, StepStatus::Running | StepStatus::Done | StepStatus::Compensating) {
+            return Ok(());
+        }
+    }
+    prune_tx(store, tx_id)
+}
+
+// the chain that casts a vote arriving on the channel, which has to be registered and part of the vote set
+fn voter_of_channel(
+    store: &dyn Storage,
+    channel_id: String,
+) -> Result<u32, ContractError> {
+    let chain_id = chain_of_channel(store, &channel_id)?
+        .ok_or_else(|| ContractError::UnknownChannel { channel_id: channel_id.clone() })?;
+    if !STATE.load(store)?.channel_ids.contains(&channel_id) {
+        return Err(ContractError::InactivePeer { chain_id });
+    }
+    Ok(chain_id)
+}
+
+
+// a vote only counts if it comes from a chain taking part in the transaction, as far as this chain knows
+fn check_participant(
+    store: &dyn Storage,
+    tx_id: TxId,
+    chain_id: u32,
+) -> Result<(), ContractError> {
+    if tx_participants(store, tx_id)?.is_some_and(|participants| !participants.contains(&chain_id)) {
+        return Err(ContractError::NotParticipant { tx_id, chain_id });
+    }
+    Ok(())
+}
+
 
+// acknowledge a phase message with the status the transaction has on this chain after handling it
+fn ack_tx(
+    store: &dyn Storage,
+    tx_id: TxId,
+) -> Result<Binary, ContractError> {
+    let status = txs().load(store, tx_id)?.status;
+    Ok(to_json_binary(&AcknowledgementMsg::Ok(MsgQueueResponse { tx_id, status }))?)
 }
 
+
+fn encode_ibc_error(msg: impl Into<String>) -> Binary {
+    // this cannot error, unwrap to keep the interface simple
+    to_json_binary(&ContractResult::<()>::Err(msg.into())).unwrap()
+}
+
+
 pub fn check_dirty(
     store: &mut dyn Storage,
     env: Env,
-    tx_id: u32,
-    timeout: IbcTimeout,
-) -> Result<Vec<IbcMsg>, ContractError> {
+    tx_id: TxId,
+) -> Result<Vec<SubMsg>, ContractError> {
    
     let mut state = STATE.load(store)?;
-    // check if dirty votes map for the key value is empty, if so, use mayload
-    let mut msgs:Vec<IbcMsg> = Vec::new();
-    let dirty_votes = state.dirty_votes;
-    if dirty_votes == state.node_number{
-        let is_top = check_queue_top(state.dirty_tx_queue.clone(), tx_id);
+    let mut msgs:Vec<SubMsg> = Vec::new();
+    let dirty_votes = DIRTY_VOTES_MAP.may_load(store, tx_id)?.unwrap_or_default();
+    if dirty_votes == tx_quorum(store, tx_id)?{
+        let is_top = check_queue_top(&state.dirty_tx_queue, tx_id);
+        // if the transaction is at the top of the dirty queue, remove it and move it to the prepare queue;
+        // if not, it waits for the transactions before it, which every chain orders the same way
         if is_top{
-            // if the transaction is at the top of the dirty queue, remove it and move it to the prepare queue
-           // if not, remove it and abort all subsequent transactions in the dirty queue, including it
-            state.prepare_tx_queue.push(state.dirty_tx_queue.get(0).unwrap().clone());
+            state.dirty_tx_queue.remove(0);
+            enqueue(store, &mut state.prepare_tx_queue, tx_id)?;
             STATE.save(store, &state)?;
-            msgs = upon_dirty_success(store, tx_id, timeout.clone()).unwrap();
-
-        } else {
-            let removed_elements = remove_all_pending_elements(&mut state.dirty_tx_queue, tx_id);
-            STATE.save(store, &state)?;
-            for tx_id in removed_elements{
-                msgs.append(&mut upon_failure(store, env.block.time.clone(), tx_id, timeout.clone()).unwrap());
+            transition_tx(store, tx_id, TxStatus::Prepared, env.block.time)?;
+            if tx_role(store, tx_id)? == Role::Coordinator {
+                msgs = broadcast(store, &env, tx_id, &Op::Prepare { value: tx_id })?;
+            }
+            msgs.append(&mut upon_dirty_success(store, &env, tx_id)?);
+            // the next transaction may have collected its votes already
+            if let Some(next) = STATE.load(store)?.dirty_tx_queue.first().copied() {
+                msgs.append(&mut check_dirty(store, env.clone(), next)?);
             }
-
-            
         }
     }
     Ok(msgs)
@@ -379,60 +1077,263 @@ pub fn check_dirty(
 }
 
 
+pub fn check_prepare(
+    store: &mut dyn Storage,
+    env: &Env,
+    tx_id: TxId,
+) -> Result<Vec<SubMsg>, ContractError> {
+
+    let state = STATE.load(store)?;
+    let mut msgs: Vec<SubMsg> = Vec::new();
+    let prepare_votes = PREPARE_VOTES_MAP.may_load(store, tx_id)?.unwrap_or_default();
+    // if the number of votes is equal to the number of nodes, the transaction is committed and every peer is told about the decision,
+    // unless the 3PC first makes sure that every chain knows it
+    if prepare_votes == tx_quorum(store, tx_id)? && check_queue_top(&state.prepare_tx_queue, tx_id){
+        msgs = match CONFIG.load(store)?.three_phase_timeout {
+            Some(_) => pre_commit(store, env, tx_id)?,
+            None => commit_everywhere(store, env, tx_id)?,
+        };
+    }
+    Ok(msgs)
+}
 
+// 3PC: this chain knows that every chain is prepared, and tells them; a coordinator only tells the participants once it has heard from all of them
+fn pre_commit(
+    store: &mut dyn Storage,
+    env: &Env,
+    tx_id: TxId,
+) -> Result<Vec<SubMsg>, ContractError> {
+    transition_tx(store, tx_id, TxStatus::PreCommitted, env.block.time)?;
+    let mut msgs = broadcast(store, env, tx_id, &Op::PreCommit { value: tx_id })?;
+    let chain_id = STATE.load(store)?.chain_id;
+    add_vote(store, &PRE_COMMIT_VOTES_MAP, &PRE_COMMIT_VOTERS, tx_id, chain_id, env.block.time)?;
+    msgs.append(&mut check_pre_commit(store, env, tx_id)?);
+    Ok(msgs)
+}
 
-pub fn upon_dirty_success(
+// 3PC: commit once every chain has pre-committed; a participant waits for the decision of the coordinator instead
+fn check_pre_commit(
+    store: &mut dyn Storage,
+    env: &Env,
+    tx_id: TxId,
+) -> Result<Vec<SubMsg>, ContractError> {
+    let pre_commit_votes = PRE_COMMIT_VOTES_MAP.may_load(store, tx_id)?.unwrap_or_default();
+    let status = load_tx(store, tx_id, env.block.time)?.status;
+    if status == TxStatus::PreCommitted && pre_commit_votes == tx_quorum(store, tx_id)? && tx_role(store, tx_id)? != Role::Participant {
+        return commit_everywhere(store, env, tx_id);
+    }
+    Ok(vec![])
+}
+
+// commit the transaction here and tell every peer about the decision
+pub fn commit_everywhere(
+    store: &mut dyn Storage,
+    env: &Env,
+    tx_id: TxId,
+) -> Result<Vec<SubMsg>, ContractError> {
+    let mut msgs = broadcast(store, env, tx_id, &Op::Commit { value: tx_id })?;
+    msgs.append(&mut commit_tx(store, env, tx_id)?);
+    Ok(msgs)
+}
+
+// remove the transaction from the prepare queue and mark it as committed, whether the decision was reached here or learned from a peer
+// moreover, if a subsequent transaction becomes the top of the prepare_tx_queue, vote for it and send the prepare success message to other blockchains.
+fn commit_tx(
     store: &mut dyn Storage,
-    tx_id: u32,
-    timeout: IbcTimeout,
-) ->  Result<Vec<IbcMsg>, ContractError>  {
+    env: &Env,
+    tx_id: TxId,
+) -> Result<Vec<SubMsg>, ContractError> {
     let mut state = STATE.load(store)?;
-    let is_top =  check_queue_top(state.prepare_tx_queue.clone(), tx_id);
-    let mut msgs: Vec<IbcMsg> = Vec::new();
+    let was_top = check_queue_top(&state.prepare_tx_queue, tx_id);
+    state.prepare_tx_queue.retain(|id| *id != tx_id);
+    STATE.save(store, &state)?;
+
+    let mut msgs: Vec<SubMsg> = Vec::new();
+    // the portion of the transaction that belongs to this chain already ran before this chain voted for it
+    transition_tx(store, tx_id, TxStatus::Committed, env.block.time)?;
+
+    if was_top {
+        if let Some(next) = state.prepare_tx_queue.first(){
+            msgs.append(&mut upon_dirty_success(store, env, *next)?);
+        }
+    }
+    Ok(msgs)
+}
+
+
+
+// the messages the transaction asks this chain to run, or the ones that undo them, if it carries any; the steps of a saga already ran
+pub fn local_msgs(
+    store: &dyn Storage,
+    chain_id: u32,
+    tx_id: TxId,
+    compensation: bool,
+) -> StdResult<Vec<CosmosMsg>> {
+    let payload = TX_PAYLOADS.may_load(store, tx_id)?;
+    Ok(payload
+        .into_iter()
+        .filter(|tx| !tx.saga)
+        .flat_map(|tx| tx.msgs)
+        .filter(|chain_msgs| chain_msgs.chain_id == chain_id)
+        .flat_map(|chain_msgs| if compensation { chain_msgs.compensation } else { chain_msgs.msgs })
+        .collect())
+}
+
+
+
+// run the local portion, or its compensation
+pub fn execute_locally(
+    store: &mut dyn Storage,
+    env: &Env,
+    tx_id: TxId,
+    compensation: bool,
+) -> StdResult<SubMsg> {
+    let msg = call_self(store, env, &ExecuteMsg::ExecuteLocal { tx_id, compensation })?;
+    // a compensation that fails does not stop the abortion, its error is only kept on record
+    match compensation {
+        true => PENDING_COMPENSATIONS.save(store, msg.id, &tx_id)?,
+        false => PENDING_REPLIES.save(store, msg.id, &tx_id)?,
+    }
+    Ok(msg)
+}
+
+
+
+pub fn upon_dirty_success(
+    store: &mut dyn Storage,
+    env: &Env,
+    tx_id: TxId,
+) ->  Result<Vec<SubMsg>, ContractError>  {
+    let state = STATE.load(store)?;
+    let is_top =  check_queue_top(&state.prepare_tx_queue, tx_id);
+    let mut msgs: Vec<SubMsg> = Vec::new();
     if is_top{
-        // let state = STATE.load(store)?;
-
-        for channel_id in state.channel_ids.iter() {
-            let packet = Op::PrepareSuccess { value: tx_id };
-            let ibc_msg = IbcMsg::SendPacket {
-                channel_id: channel_id.clone(),
-                data: to_binary(&packet).unwrap(),
-                timeout: timeout.clone(),
-            };
-            // 将新创建的消息添加到msgs向量中
-            msgs.push(ibc_msg);
+        // the local portion runs before this chain votes, so that a failure still aborts the transaction everywhere;
+        // the vote is cast once it succeeds, in the reply entry point
+        let executed = txs().load(store, tx_id)?.executed_at.is_some();
+        if !executed && !local_msgs(store, state.chain_id, tx_id, false)?.is_empty() {
+            return Ok(vec![execute_locally(store, env, tx_id, false)?]);
+        }
+        // the coordinator tells its prepare vote along with the decision
+        if tx_role(store, tx_id)? != Role::Coordinator {
+            msgs = broadcast(store, env, tx_id, &Op::PrepareSuccess { value: tx_id })?;
         }
-        state.prepare_votes+=1;
-        STATE.save(store, &state)?;
+        add_vote(store, &PREPARE_VOTES_MAP, &PREPARE_VOTERS, tx_id, state.chain_id, env.block.time)?;
+        // the local vote may be the last one missing
+        msgs.append(&mut check_prepare(store, env, tx_id)?);
     
     }
     Ok(msgs)
   
 }
 
-pub fn upon_failure(
+// take an aborted transaction out of the queues, and let the next transaction vote if it becomes the top of the prepare queue
+pub fn remove_from_queues(
     store: &mut dyn Storage,
-    time: Timestamp,
-    tx_id: u32,
-    timeout: IbcTimeout,
-) ->  Result<Vec<IbcMsg>, ContractError> {
-    
-    // instantiate a vector containing IbcMsg
+    env: &Env,
+    tx_id: TxId,
+) -> Result<Vec<SubMsg>, ContractError> {
     let mut state = STATE.load(store)?;
-    let mut msgs: Vec<IbcMsg> = Vec::new();
-    for channel_id in state.channel_ids.iter() {
-        let packet = Op::Abortion { value: tx_id };
+    let was_top = check_queue_top(&state.prepare_tx_queue, tx_id);
+    let dirty_top = state.dirty_tx_queue.first().copied();
+    state.dirty_tx_queue.retain(|id| *id != tx_id);
+    state.prepare_tx_queue.retain(|id| *id != tx_id);
+    STATE.save(store, &state)?;
+
+    let mut msgs: Vec<SubMsg> = Vec::new();
+    if was_top {
+        if let Some(next) = state.prepare_tx_queue.first() {
+            msgs = upon_dirty_success(store, env, *next)?;
+        }
+    }
+    msgs.append(&mut check_new_dirty_top(store, env, dirty_top)?);
+    Ok(msgs)
+}
+
+// abort a batch of queued transactions at once, so that only the surviving top of the prepare queue starts voting
+pub fn abort_txs(
+    store: &mut dyn Storage,
+    env: &Env,
+    tx_ids: &[TxId],
+) -> Result<Vec<SubMsg>, ContractError> {
+    let mut state = STATE.load(store)?;
+    let old_top = state.prepare_tx_queue.first().copied();
+    let dirty_top = state.dirty_tx_queue.first().copied();
+    state.dirty_tx_queue.retain(|id| !tx_ids.contains(id));
+    state.prepare_tx_queue.retain(|id| !tx_ids.contains(id));
+    STATE.save(store, &state)?;
+
+    let mut msgs: Vec<SubMsg> = Vec::new();
+    for tx_id in tx_ids {
+        msgs.append(&mut upon_failure(store, env, *tx_id)?);
+    }
+    let new_top = state.prepare_tx_queue.first().copied();
+    if let Some(next) = new_top {
+        if new_top != old_top {
+            msgs.append(&mut upon_dirty_success(store, env, next)?);
+        }
+    }
+    msgs.append(&mut check_new_dirty_top(store, env, dirty_top)?);
+    Ok(msgs)
+}
+
+// a transaction that reaches the top of the dirty queue may have collected its votes while it waited
+fn check_new_dirty_top(
+    store: &mut dyn Storage,
+    env: &Env,
+    old_top: Option<TxId>,
+) -> Result<Vec<SubMsg>, ContractError> {
+    let new_top = STATE.load(store)?.dirty_tx_queue.first().copied();
+    match new_top {
+        Some(next) if new_top != old_top => check_dirty(store, env.clone(), next),
+        _ => Ok(vec![]),
+    }
+}
+
+// send the message about the transaction to every chain that has to hear about it
+pub fn broadcast(
+    store: &mut dyn Storage,
+    env: &Env,
+    tx_id: TxId,
+    packet: &Op,
+) -> StdResult<Vec<SubMsg>> {
+    let timeout = tx_timeout(store, env, tx_id)?;
+    let data = packet_data(store, packet)?;
+    let mut msgs: Vec<SubMsg> = Vec::new();
+    for channel_id in tx_channels(store, tx_id)? {
         let ibc_msg = IbcMsg::SendPacket {
-            channel_id: channel_id.clone(),
-            data: to_binary(&packet).unwrap(),
+            channel_id,
+            data: data.clone(),
             timeout: timeout.clone(),
         };
-        // 将新创建的消息添加到msgs向量中
-        msgs.push(ibc_msg);
+        msgs.push(SubMsg::new(ibc_msg));
     }
+    Ok(msgs)
+}
 
-    state.end_time=Some(time);
-    state.aborted=true;
-    STATE.save(store, &state)?;
+pub fn upon_failure(
+    store: &mut dyn Storage,
+    env: &Env,
+    tx_id: TxId,
+) ->  Result<Vec<SubMsg>, ContractError> {
+    
+    // instantiate a vector containing the abortion messages
+    let mut msgs = broadcast(store, env, tx_id, &Op::Abortion { value: tx_id })?;
+
+    msgs.append(&mut abort_locally(store, env, tx_id)?);
     Ok(msgs)
 }
+
+// mark the transaction as aborted, and undo its local portion if it already ran
+fn abort_locally(
+    store: &mut dyn Storage,
+    env: &Env,
+    tx_id: TxId,
+) -> Result<Vec<SubMsg>, ContractError> {
+    let tx = transition_tx(store, tx_id, TxStatus::Aborted, env.block.time)?;
+    if tx.executed_at.is_none() {
+        prune_aborted(store, tx_id)?;
+        return Ok(vec![]);
+    }
+    Ok(vec![execute_locally(store, env, tx_id, true)?])
+}
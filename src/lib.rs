@@ -3,4 +3,8 @@ pub mod ibc;
 pub mod state;
 pub mod error;
 pub mod msg;
-pub mod utils;
\ No newline at end of file
+pub mod utils;
+pub mod saga;
+
+#[cfg(test)]
+mod tests;
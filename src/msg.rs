@@ -2,61 +2,230 @@
 
 use serde::{Deserialize, Serialize};
 use schemars::JsonSchema;
-use cosmwasm_std::{ContractResult, Timestamp};
+use cosmwasm_std::{Addr, ContractResult, HexBinary, IbcOrder, Timestamp};
 
-use crate::state::Transaction;
+use crate::state::{Protocol, StepStatus, TimeoutPolicy, Transaction, TxId, TxStatus};
 
-#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema, Hash)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub struct InstantiateMsg{
     pub chain_id: u32,
+    // defaults to an unordered channel
+    pub channel_order: Option<IbcOrder>,
+    // counterparty ports and connections allowed to open a channel, any if not set
+    pub allowed_ports: Option<Vec<String>>,
+    pub allowed_connections: Option<Vec<String>>,
+    pub validator: Option<String>,
+    // defaults to a timeout of 3600000 seconds
+    pub packet_timeout: Option<TimeoutPolicy>,
+    // defaults to false
+    pub content_addressed: Option<bool>,
+    // defaults to all-to-all voting
+    pub protocol: Option<Protocol>,
+    // seconds per phase of the 3PC, which defaults to the blocking 2PC
+    pub three_phase_timeout: Option<u64>,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema, Hash)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct MigrateMsg{
+    // required when the deployed contract predates the stored config
+    pub admin: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub enum ExecuteMsg{
+    // the id of the transaction is assigned by this chain and returned in the data of the response
     Input {value: Transaction},
+    // admin only, fields that are not set are left unchanged
+    UpdateConfig {channel_order: Option<IbcOrder>, allowed_ports: Option<Vec<String>>, allowed_connections: Option<Vec<String>>, packet_timeout: Option<TimeoutPolicy>, content_addressed: Option<bool>},
+    TransferAdmin {admin: String},
+    // admin only, the validator is removed if not set
+    SetValidator {validator: Option<String>},
+    // drop the chain from the vote set and close its channel
+    RemovePeer {chain_id: u32},
+    // temporarily take the chain out of the vote set, or bring it back
+    SetPeerActive {chain_id: u32, active: bool},
+    // internal, sent by the contract to itself to run the local portion of a transaction before voting for it, or to undo it once it is aborted
+    ExecuteLocal {tx_id: TxId, compensation: bool},
+    // internal, sent by the contract to itself to run a step of a saga, or its compensation
+    ExecuteStep {tx_id: TxId, step: u32, compensation: bool},
+    // ask every peer about the outcome of a transaction stuck in the prepared state
+    Resolve {tx_id: TxId},
+    // abort up to `limit` expired transactions of the dirty and prepare queues, except the ones this chain voted to prepare;
+    // under the 3PC, also settle the ones that spent too long in their phase
+    Crank {limit: Option<u32>},
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema, Hash)]
 pub enum QueryMsg{
-    GetTx{},
+    GetTx{tx_id: TxId},
     GetChainId{},
     GetNodeNumber{},
+    GetConfig{},
     GetDirtyTx{},
     GetPrepareTx{},
+    // list the transactions in ascending tx_id order, optionally only those with the given status
+    ListTxs{status: Option<TxStatus>, start_after: Option<TxId>, limit: Option<u32>},
+    // the transaction announced with this digest, in content addressed mode
+    GetTxByDigest{digest: HexBinary},
 
 }
 
+// the query every validator contract answers before this chain votes for a transaction
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ValidatorQueryMsg{
+    CanPrepare {tx: Transaction},
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct CanPrepareResponse{
+    pub can_prepare: bool,
+    pub reason: Option<String>,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct GetTxResponse{
-    pub dirty_votes: Option<u32>,
-    pub prepare_votes: Option<u32>,
-    pub committed: Option<bool>,
-    pub aborted: Option<bool>,
+    pub tx_id: TxId,
+    pub status: TxStatus,
+    pub submitter: Option<Addr>,
+    pub dirty_votes: u32,
+    pub prepare_votes: u32,
+    pub pre_commit_votes: u32,
+    // chain ids of the chains whose votes have been counted, including this chain
+    pub dirty_voters: Vec<u32>,
+    pub prepare_voters: Vec<u32>,
+    pub pre_commit_voters: Vec<u32>,
+    pub seen_at: Timestamp,
+    pub submitted_at: Option<Timestamp>,
+    pub prepared_at: Option<Timestamp>,
+    pub pre_committed_at: Option<Timestamp>,
+    pub committed_at: Option<Timestamp>,
+    pub aborted_at: Option<Timestamp>,
+    pub executed_at: Option<Timestamp>,
+    // peers that acknowledged the phase messages this chain sent for the transaction
+    pub confirmations: Vec<Confirmation>,
+    // peers that acknowledged the commit decision of this chain
+    pub decision_acks: Vec<u32>,
+    pub error: Option<String>,
+    pub digest: Option<HexBinary>,
+    // saga mode, the steps started so far as far as this chain knows
+    pub steps: Vec<SagaStepResponse>,
+    // seconds between the local submission and the commit or abortion
     pub time: Option<u64>,
-    pub start_time: Timestamp,
-    pub end_time: Option<Timestamp>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct SagaStepResponse{
+    pub step: u32,
+    pub chain_id: u32,
+    pub status: StepStatus,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ListTxsResponse{
+    pub txs: Vec<GetTxResponse>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub enum Op{
-    // sent after processing an input
-    DirtySuccess{value: u32},
+    // sent after processing an input, the origin of the transaction also hands its payload over so that the peers can join it
+    DirtySuccess{value: TxId, #[serde(default)] tx: Option<Transaction>},
     //sent after acquiring all dirty votes but fail or find out that a previous prepared state fails
-    Abortion{value: u32},
+    Abortion{value: TxId},
     //sent after acquiring all dirty votes and succeed
-    PrepareSuccess{value: u32},    
+    PrepareSuccess{value: TxId},    
+    //sent after acquiring all prepare votes, so that chains which missed some of them learn the decision
+    Commit{value: TxId},
+    //sent back to the chain that announced the decision
+    DecisionAck{value: TxId},
+    //sent by a prepared chain that waits too long for the outcome, answered in the acknowledgement
+    QueryDecision{value: TxId},
+    //coordinator mode, sent by the origin to announce the transaction before it votes
+    Propose{value: TxId, tx: Transaction},
+    //coordinator mode, sent by the origin once every participant voted for the dirty phase, standing for its own dirty vote
+    Prepare{value: TxId},
+    //3PC, sent after acquiring all prepare votes, so that every chain knows that everyone is prepared before anyone commits
+    PreCommit{value: TxId},
+    //saga mode, sent by the origin to the chain that runs the step
+    SagaStep{value: TxId, tx: Transaction, step: u32},
+    //saga mode, sent by the origin to undo a step that ran
+    Compensate{value: TxId, step: u32},
+    //saga mode, sent back to the origin once the step or its compensation ran
+    StepResult{value: TxId, step: u32, compensation: bool, error: Option<String>},
 
     WhoAmI{chain_id: u32},
 }
 
+// the data of every packet, stamped with the lamport clock of the sender
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PacketData{
+    pub lamport: u64,
+    pub op: Op,
+    // the digest of the transaction the packet is about, as announced by its origin;
+    // every chain that knows it checks it, so that all the packets of a transaction refer to the same content
+    #[serde(default)]
+    pub digest: Option<HexBinary>,
+}
+
+impl Op {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Op::DirtySuccess { .. } => "dirty_success",
+            Op::Abortion { .. } => "abortion",
+            Op::PrepareSuccess { .. } => "prepare_success",
+            Op::Commit { .. } => "commit",
+            Op::DecisionAck { .. } => "decision_ack",
+            Op::QueryDecision { .. } => "query_decision",
+            Op::Propose { .. } => "propose",
+            Op::Prepare { .. } => "prepare",
+            Op::PreCommit { .. } => "pre_commit",
+            Op::SagaStep { .. } => "saga_step",
+            Op::Compensate { .. } => "compensate",
+            Op::StepResult { .. } => "step_result",
+            Op::WhoAmI { .. } => "who_am_i",
+        }
+    }
+
+    // the transaction the message is about, if any
+    pub fn tx_id(&self) -> Option<TxId> {
+        match self {
+            Op::DirtySuccess { value, .. }
+            | Op::Abortion { value }
+            | Op::PrepareSuccess { value }
+            | Op::Commit { value }
+            | Op::DecisionAck { value }
+            | Op::QueryDecision { value }
+            | Op::Propose { value, .. }
+            | Op::Prepare { value }
+            | Op::PreCommit { value }
+            | Op::SagaStep { value, .. }
+            | Op::Compensate { value, .. }
+            | Op::StepResult { value, .. } => Some(*value),
+            Op::WhoAmI { .. } => None,
+        }
+    }
+}
+
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct WhoAmIResponse {
 }
 
+// acknowledgement of a phase message, carrying the status of the transaction on the receiving chain after handling it
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct MsgQueueResponse{
+    pub tx_id: TxId,
+    pub status: TxStatus,
+}
 
+// a peer that acknowledged a phase message of the transaction
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Confirmation{
+    pub chain_id: u32,
+    pub phase: String,
+    pub status: TxStatus,
 }
 pub type AcknowledgementMsg<T> = ContractResult<T>;
\ No newline at end of file
@@ -0,0 +1,256 @@
+use cosmwasm_std::{Addr, CosmosMsg, DepsMut, Env, HexBinary, IbcMsg, Order, StdResult, Storage, SubMsg};
+use cw_storage_plus::Bound;
+
+use crate::contract::check_can_prepare;
+use crate::error::ContractError;
+use crate::ibc::{broadcast, prune_aborted};
+use crate::msg::{ExecuteMsg, Op};
+use crate::state::{txs, ChainMsgs, SagaReply, StepInfo, StepStatus, Transaction, TxId, TxStatus, CHANNELS, SAGA_REPLIES, SAGA_STEPS, STATE, TX_DIGESTS, TX_PAYLOADS};
+use crate::utils::{call_self, packet_data, transition_tx, tx_timeout};
+
+// saga mode: the steps of a transaction run one after the other as soon as it is submitted, without locking any chain;
+// the origin drives them, and once a step fails it runs the compensations of the completed steps in reverse order
+
+
+// origin: take the transaction submitted here and run its first step, unless the application refuses it, in which case the reason is returned
+pub fn start_saga(
+    deps: DepsMut,
+    env: &Env,
+    tx_id: TxId,
+    payload: Transaction,
+    digest: Option<HexBinary>,
+    submitter: Addr,
+) -> Result<(Vec<SubMsg>, Option<String>), ContractError> {
+    let mut tx = transition_tx(deps.storage, tx_id, TxStatus::Dirty, env.block.time)?;
+    tx.submitter = Some(submitter);
+    if let Some(digest) = &digest {
+        TX_DIGESTS.save(deps.storage, digest.as_slice(), &tx_id)?;
+    }
+    tx.digest = digest;
+    tx.participants = Some(payload.participants.clone());
+    txs().save(deps.storage, tx_id, &tx)?;
+    TX_PAYLOADS.save(deps.storage, tx_id, &payload)?;
+    if let Some(reason) = check_can_prepare(deps.as_ref(), &payload)? {
+        let mut tx = transition_tx(deps.storage, tx_id, TxStatus::Aborted, env.block.time)?;
+        tx.error = Some(reason.clone());
+        txs().save(deps.storage, tx_id, &tx)?;
+        prune_aborted(deps.storage, tx_id)?;
+        return Ok((vec![], Some(reason)));
+    }
+    Ok((run_step(deps.storage, env, tx_id, 0)?, None))
+}
+
+// origin: run the step on its chain, or settle the saga once every step is done
+fn run_step(
+    store: &mut dyn Storage,
+    env: &Env,
+    tx_id: TxId,
+    step: u32,
+) -> Result<Vec<SubMsg>, ContractError> {
+    let payload = TX_PAYLOADS.load(store, tx_id)?;
+    let chain_id = match payload.msgs.get(step as usize) {
+        Some(chain_msgs) => chain_msgs.chain_id,
+        None => return finish_saga(store, env, tx_id),
+    };
+    SAGA_STEPS.save(store, (tx_id, step), &StepInfo { chain_id, status: StepStatus::Running, error: None })?;
+    if chain_id == STATE.load(store)?.chain_id {
+        return Ok(vec![execute_step(store, env, tx_id, step, false)?]);
+    }
+    let packet = Op::SagaStep { value: tx_id, tx: payload, step };
+    Ok(vec![send_to(store, env, tx_id, chain_id, &packet)?])
+}
+
+// origin: every step ran, so the chains that ran one commit the transaction on their side as well
+fn finish_saga(
+    store: &mut dyn Storage,
+    env: &Env,
+    tx_id: TxId,
+) -> Result<Vec<SubMsg>, ContractError> {
+    transition_tx(store, tx_id, TxStatus::Prepared, env.block.time)?;
+    transition_tx(store, tx_id, TxStatus::Committed, env.block.time)?;
+    Ok(broadcast(store, env, tx_id, &Op::Commit { value: tx_id })?)
+}
+
+// origin: undo the last completed step before the given one, if any is left
+fn compensate_before(
+    store: &mut dyn Storage,
+    env: &Env,
+    tx_id: TxId,
+    step: u32,
+) -> Result<Vec<SubMsg>, ContractError> {
+    let done = SAGA_STEPS
+        .prefix(tx_id)
+        .range(store, None, Some(Bound::exclusive(step)), Order::Descending)
+        .find(|item| item.as_ref().map_or(true, |(_, info)| info.status == StepStatus::Done))
+        .transpose()?;
+    let (step, mut info) = match done {
+        Some(done) => done,
+        None => return Ok(vec![]),
+    };
+    info.status = StepStatus::Compensating;
+    SAGA_STEPS.save(store, (tx_id, step), &info)?;
+    if info.chain_id == STATE.load(store)?.chain_id {
+        return Ok(vec![execute_step(store, env, tx_id, step, true)?]);
+    }
+    Ok(vec![send_to(store, env, tx_id, info.chain_id, &Op::Compensate { value: tx_id, step })?])
+}
+
+// origin: record the outcome of a step, then move on to the next step, or undo the completed ones
+pub fn on_step_result(
+    store: &mut dyn Storage,
+    env: &Env,
+    tx_id: TxId,
+    step: u32,
+    compensation: bool,
+    error: Option<String>,
+) -> Result<Vec<SubMsg>, ContractError> {
+    let mut info = SAGA_STEPS.may_load(store, (tx_id, step))?
+        .ok_or(ContractError::InvalidStep { tx_id, step })?;
+    // a result that was already recorded is ignored
+    let expected = if compensation { StepStatus::Compensating } else { StepStatus::Running };
+    if info.status != expected {
+        return Ok(vec![]);
+    }
+    info.status = step_outcome(compensation, error.is_some());
+    info.error = error.clone();
+    SAGA_STEPS.save(store, (tx_id, step), &info)?;
+
+    let msgs = match (compensation, error) {
+        (false, None) => return run_step(store, env, tx_id, step + 1),
+        (false, Some(error)) => {
+            let mut tx = transition_tx(store, tx_id, TxStatus::Aborted, env.block.time)?;
+            tx.error = Some(error);
+            txs().save(store, tx_id, &tx)?;
+            compensate_before(store, env, tx_id, step)?
+        },
+        // a compensation that failed stays on record, but the steps before it are still undone
+        (true, _) => compensate_before(store, env, tx_id, step)?,
+    };
+    // once every completed step is undone, the saga is over
+    prune_aborted(store, tx_id)?;
+    Ok(msgs)
+}
+
+// another chain: check that the origin asks this chain to run the step, or to undo it once it is done
+pub fn receive_step(
+    store: &mut dyn Storage,
+    env: &Env,
+    tx_id: TxId,
+    step: u32,
+    compensation: bool,
+) -> Result<SubMsg, ContractError> {
+    let chain_id = STATE.load(store)?.chain_id;
+    if load_step(store, tx_id, step)?.chain_id != chain_id {
+        return Err(ContractError::InvalidStep { tx_id, step });
+    }
+    let status = SAGA_STEPS.may_load(store, (tx_id, step))?.map(|info| info.status);
+    let next = match (compensation, status) {
+        (false, None) => StepStatus::Running,
+        (true, Some(StepStatus::Done)) => StepStatus::Compensating,
+        _ => return Err(ContractError::InvalidStep { tx_id, step }),
+    };
+    let tx_status = txs().load(store, tx_id)?.status;
+    if !compensation && !matches!(tx_status, TxStatus::Dirty | TxStatus::Prepared) {
+        return Err(ContractError::InvalidTransition { tx_id, from: tx_status, to: TxStatus::Prepared });
+    }
+    SAGA_STEPS.save(store, (tx_id, step), &StepInfo { chain_id, status: next, error: None })?;
+    Ok(execute_step(store, env, tx_id, step, compensation)?)
+}
+
+// the outcome of a step that ran on this chain, which the origin learns by a packet unless it ran the step itself
+pub fn step_reply(
+    store: &mut dyn Storage,
+    env: &Env,
+    reply: SagaReply,
+    error: Option<String>,
+) -> Result<Vec<SubMsg>, ContractError> {
+    let SagaReply { tx_id, step, compensation } = reply;
+    if tx_id.origin == STATE.load(store)?.chain_id {
+        return on_step_result(store, env, tx_id, step, compensation, error);
+    }
+    let mut info = SAGA_STEPS.load(store, (tx_id, step))?;
+    info.status = step_outcome(compensation, error.is_some());
+    info.error = error.clone();
+    SAGA_STEPS.save(store, (tx_id, step), &info)?;
+
+    // the transaction is prepared here as long as its steps succeed, and aborted once one of them fails or is undone
+    let mut tx = txs().load(store, tx_id)?;
+    match (compensation, &error) {
+        (false, None) if tx.status == TxStatus::Dirty => tx = transition_tx(store, tx_id, TxStatus::Prepared, env.block.time)?,
+        (false, None) => {},
+        _ if !tx.status.is_final() => tx = transition_tx(store, tx_id, TxStatus::Aborted, env.block.time)?,
+        _ => {},
+    }
+    if error.is_some() {
+        tx.error = error.clone();
+        txs().save(store, tx_id, &tx)?;
+    }
+    let packet = Op::StepResult { value: tx_id, step, compensation, error };
+    let msg = send_to(store, env, tx_id, tx_id.origin, &packet)?;
+    prune_aborted(store, tx_id)?;
+    Ok(vec![msg])
+}
+
+// the messages of the step, or of its compensation
+pub fn step_msgs(
+    store: &dyn Storage,
+    tx_id: TxId,
+    step: u32,
+    compensation: bool,
+) -> Result<Vec<CosmosMsg>, ContractError> {
+    let chain_msgs = load_step(store, tx_id, step)?;
+    if compensation {
+        Ok(chain_msgs.compensation)
+    } else {
+        Ok(chain_msgs.msgs)
+    }
+}
+
+fn load_step(
+    store: &dyn Storage,
+    tx_id: TxId,
+    step: u32,
+) -> Result<ChainMsgs, ContractError> {
+    TX_PAYLOADS.may_load(store, tx_id)?
+        .and_then(|tx| tx.msgs.into_iter().nth(step as usize))
+        .ok_or(ContractError::InvalidStep { tx_id, step })
+}
+
+fn step_outcome(compensation: bool, failed: bool) -> StepStatus {
+    match (compensation, failed) {
+        (false, false) => StepStatus::Done,
+        (false, true) => StepStatus::Failed,
+        (true, false) => StepStatus::Compensated,
+        (true, true) => StepStatus::CompensationFailed,
+    }
+}
+
+// run the step, or its compensation
+fn execute_step(
+    store: &mut dyn Storage,
+    env: &Env,
+    tx_id: TxId,
+    step: u32,
+    compensation: bool,
+) -> StdResult<SubMsg> {
+    let msg = call_self(store, env, &ExecuteMsg::ExecuteStep { tx_id, step, compensation })?;
+    SAGA_REPLIES.save(store, msg.id, &SagaReply { tx_id, step, compensation })?;
+    Ok(msg)
+}
+
+// steps are exchanged between the origin and one chain at a time
+fn send_to(
+    store: &mut dyn Storage,
+    env: &Env,
+    tx_id: TxId,
+    chain_id: u32,
+    packet: &Op,
+) -> Result<SubMsg, ContractError> {
+    let channel_id = CHANNELS.may_load(store, chain_id)?
+        .ok_or(ContractError::UnknownChain { chain_id })?;
+    Ok(SubMsg::new(IbcMsg::SendPacket {
+        channel_id,
+        data: packet_data(store, packet)?,
+        timeout: tx_timeout(store, env, tx_id)?,
+    }))
+}
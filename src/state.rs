@@ -1,9 +1,9 @@
-
+use std::fmt;
 
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use cosmwasm_std::Timestamp;
-use cw_storage_plus::{Item, Map};
+use cosmwasm_std::{Addr, BlockInfo, CosmosMsg, HexBinary, IbcOrder, IbcTimeoutBlock, StdResult, Timestamp};
+use cw_storage_plus::{Index, IndexList, IndexedMap, Item, Key, KeyDeserialize, Map, MultiIndex, Prefixer, PrimaryKey};
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct State{
@@ -12,11 +12,37 @@ pub struct State{
     pub channel_ids: Vec<String>,
 
 
+    // both queues are ordered by the lamport time of the transactions, then by their origin, the same way on every chain
+    pub dirty_tx_queue: Vec<TxId>,
+    pub prepare_tx_queue: Vec<TxId>,
+    // the lamport clock of this chain, advanced by every input and packet and merged with the clock of every packet received
+    #[serde(default)]
+    pub lamport: u64,
+}
+
+impl State {
+    //new
+    pub fn new(chain_id: u32) -> Self {
+        State {
+            node_number: 1,
+            chain_id,
+            channel_ids: vec![],
+            dirty_tx_queue: vec![],
+            prepare_tx_queue: vec![],
+            lamport: 0,
+        }
+    }
+}
+
+// the layout of 0.0.x, where the votes and the outcome were kept once for the whole contract
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct LegacyState{
+    pub node_number: u32,
+    pub chain_id: u32,
+    pub channel_ids: Vec<String>,
     pub dirty_tx_queue: Vec<u32>,
     pub prepare_tx_queue: Vec<u32>,
     pub commit_tx_queue: Vec<u32>,
-
-
     pub dirty_votes: u32,
     pub prepare_votes: u32,
     pub start_time: Timestamp,
@@ -24,49 +50,375 @@ pub struct State{
     pub committed: bool,
     pub aborted: bool,
     pub time: u64,
+}
 
+// restrictions on the channels that are allowed to join the vote set
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Config{
+    // the only account allowed to change the config and the membership
+    pub admin: Addr,
+    pub channel_order: IbcOrder,
+    // an empty list accepts any counterparty port or connection
+    pub allowed_ports: Vec<String>,
+    pub allowed_connections: Vec<String>,
+    // application contract asked whether this chain can take part in a transaction, every transaction is accepted if not set
+    pub validator: Option<Addr>,
+    // timeout of the packets sent to the peers, a transaction may override it
+    #[serde(default)]
+    pub packet_timeout: TimeoutPolicy,
+    // announce the transactions submitted here with the sha256 digest of their content, and refuse to join any peer transaction without one
+    #[serde(default)]
+    pub content_addressed: bool,
+    // chosen at instantiation, all the chains of the vote set have to run the same one
+    #[serde(default)]
+    pub protocol: Protocol,
+    // seconds a transaction may spend in a phase under the non-blocking 3PC, which adds a pre-commit phase after the prepare one;
+    // the blocking 2PC runs if not set, also chosen at instantiation
+    #[serde(default)]
+    pub three_phase_timeout: Option<u64>,
 }
 
-impl State {
-    //new
-    pub fn new(chain_id: u32, start: Timestamp) -> Self {
-        State {
-            node_number: 1,
-            chain_id,
-            channel_ids: vec![],
-            dirty_tx_queue: vec![],
-            prepare_tx_queue: vec![],
-            commit_tx_queue: vec![],
-            dirty_votes: 0,
-            prepare_votes: 0,
-            start_time: start,
-            end_time: None, 
-            committed: false,
-            aborted: false,
-            time: 0,
-        }
+// how the chains exchange their votes
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Protocol{
+    // every participant sends its votes to every other participant
+    #[default]
+    AllToAll,
+    // the origin collects the votes, then tells the other participants when to prepare and what the decision is
+    Coordinator,
+}
+
+// the part this chain plays in a transaction
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Role{
+    // all-to-all voting, every participant plays the same part
+    Peer,
+    // the origin of the transaction in coordinator mode
+    Coordinator,
+    // any other participant in coordinator mode, which only talks to the coordinator
+    Participant,
+}
+
+// at least one of the two must be set, the packet times out at whichever is reached first
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct TimeoutPolicy{
+    // seconds after the time of the block sending the packet
+    pub seconds: Option<u64>,
+    // height of the counterparty chain
+    pub height: Option<IbcTimeoutBlock>,
+}
+
+impl TimeoutPolicy {
+    pub fn is_valid(&self) -> bool {
+        self.seconds.is_some() || self.height.is_some()
+    }
+}
+
+impl Default for TimeoutPolicy {
+    fn default() -> Self {
+        TimeoutPolicy { seconds: Some(PACKET_LIFETIME), height: None }
     }
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema, Hash)]
+pub const PACKET_LIFETIME: u64 = 3600000;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub struct Transaction{
-    pub tx_id: u32,
+    // chain_ids of the chains taking part in the transaction, the only ones that ever hear about it;
+    // every member of the vote set takes part if empty
+    #[serde(default)]
+    pub participants: Vec<u32>,
+    // the messages every participating chain runs before it votes to prepare the transaction
+    #[serde(default)]
+    pub msgs: Vec<ChainMsgs>,
+    // the transaction is aborted by a crank if it is still in flight past this point
+    #[serde(default)]
+    pub expires_at: Option<Expiration>,
+    // replaces the packet timeout of the config for every packet about this transaction
+    #[serde(default)]
+    pub packet_timeout: Option<TimeoutPolicy>,
+    // run every entry of msgs as a step right away instead of locking the chains with the 2PC,
+    // and undo the completed steps with their compensations if one of them fails
+    #[serde(default)]
+    pub saga: bool,
+    // the lamport time of the submission, assigned by the origin
+    #[serde(default)]
+    pub lamport: u64,
+}
+
+// transactions are numbered by the chain they are submitted on, so that two chains never hand out the same id
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, JsonSchema)]
+pub struct TxId{
+    // chain_id of the chain the transaction was submitted on
+    pub origin: u32,
+    // assigned by that chain, one after the other
+    pub seq: u32,
+}
+
+// the origin of the transactions migrated from 0.0.x, whose ids were chosen by the users and are the same on every chain;
+// no chain can take it as its chain_id
+pub const LEGACY_ORIGIN: u32 = u32::MAX;
+
+impl TxId {
+    fn to_bytes(self) -> [u8; 8] {
+        ((u64::from(self.origin) << 32) | u64::from(self.seq)).to_be_bytes()
+    }
+}
+
+impl fmt::Display for TxId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.origin, self.seq)
+    }
+}
+
+// stored as a single key, so that the transactions are listed by origin and then by sequence
+impl<'a> PrimaryKey<'a> for TxId {
+    type Prefix = ();
+    type SubPrefix = ();
+    type Suffix = Self;
+    type SuperSuffix = Self;
+
+    fn key(&self) -> Vec<Key<'_>> {
+        vec![Key::Val64(self.to_bytes())]
+    }
+}
+
+impl<'a> Prefixer<'a> for TxId {
+    fn prefix(&self) -> Vec<Key<'_>> {
+        vec![Key::Val64(self.to_bytes())]
+    }
+}
+
+impl KeyDeserialize for TxId {
+    type Output = TxId;
+
+    fn from_vec(value: Vec<u8>) -> StdResult<Self::Output> {
+        let key = u64::from_vec(value)?;
+        Ok(TxId { origin: (key >> 32) as u32, seq: key as u32 })
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Expiration{
+    AtHeight(u64),
+    AtTime(Timestamp),
+}
+
+impl Expiration {
+    pub fn is_expired(&self, block: &BlockInfo) -> bool {
+        match self {
+            Expiration::AtHeight(height) => block.height >= *height,
+            Expiration::AtTime(time) => block.time >= *time,
+        }
+    }
+}
+
+// the portion of a transaction that belongs to one chain
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct ChainMsgs{
+    pub chain_id: u32,
+    pub msgs: Vec<CosmosMsg>,
+    // the messages that undo msgs once they ran, if a later step of a saga fails or the transaction is aborted after this chain voted for it
+    #[serde(default)]
+    pub compensation: Vec<CosmosMsg>,
 }
 pub const STATE: Item<State> = Item::new("state");
+pub const LEGACY_STATE: Item<LegacyState> = Item::new("state");
+pub const CONFIG: Item<Config> = Item::new("config");
+
+// the lifecycle of a transaction as seen by this chain
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, JsonSchema, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum TxStatus{
+    // known only through the votes of other chains, not submitted locally yet
+    Pending,
+    // submitted locally and waiting in the dirty queue
+    Dirty,
+    // acquired all dirty votes and waiting in the prepare queue
+    Prepared,
+    // 3PC only, acquired all prepare votes and waiting for every chain to know it
+    PreCommitted,
+    Committed,
+    Aborted,
+}
+
+impl TxStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TxStatus::Pending => "pending",
+            TxStatus::Dirty => "dirty",
+            TxStatus::Prepared => "prepared",
+            TxStatus::PreCommitted => "pre_committed",
+            TxStatus::Committed => "committed",
+            TxStatus::Aborted => "aborted",
+        }
+    }
+
+    pub fn is_final(&self) -> bool {
+        matches!(self, TxStatus::Committed | TxStatus::Aborted)
+    }
+
+    // a transaction moves forward one phase at a time: pending -> dirty -> prepared -> (pre_committed ->) committed,
+    // and it can be aborted from any status that is not final yet
+    pub fn can_transition_to(&self, next: TxStatus) -> bool {
+        match next {
+            TxStatus::Pending => false,
+            TxStatus::Dirty => *self == TxStatus::Pending,
+            TxStatus::Prepared => *self == TxStatus::Dirty,
+            TxStatus::PreCommitted => *self == TxStatus::Prepared,
+            TxStatus::Committed => matches!(self, TxStatus::Prepared | TxStatus::PreCommitted),
+            TxStatus::Aborted => !self.is_final(),
+        }
+    }
+}
+
+impl fmt::Display for TxStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct TxInfo{
+    pub status: TxStatus,
+    // the sender of the local input, none if the transaction was only seen through votes
+    pub submitter: Option<Addr>,
+    pub seen_at: Timestamp,
+    pub submitted_at: Option<Timestamp>,
+    pub prepared_at: Option<Timestamp>,
+    #[serde(default)]
+    pub pre_committed_at: Option<Timestamp>,
+    pub committed_at: Option<Timestamp>,
+    pub aborted_at: Option<Timestamp>,
+    // when the local portion ran, which happens before this chain votes to prepare the transaction
+    #[serde(default)]
+    pub executed_at: Option<Timestamp>,
+    // why this chain refused the transaction or failed to run its portion
+    pub error: Option<String>,
+    // sha256 of the payload, the origin and the sequence, set if the origin announced it
+    #[serde(default)]
+    pub digest: Option<HexBinary>,
+    // the participants listed by the payload once this chain joined, kept after the payload is pruned;
+    // empty if every member of the vote set takes part
+    #[serde(default)]
+    pub participants: Option<Vec<u32>>,
+}
+
+impl TxInfo {
+    pub fn new(seen_at: Timestamp) -> Self {
+        TxInfo {
+            status: TxStatus::Pending,
+            submitter: None,
+            seen_at,
+            submitted_at: None,
+            prepared_at: None,
+            pre_committed_at: None,
+            committed_at: None,
+            aborted_at: None,
+            executed_at: None,
+            error: None,
+            digest: None,
+            participants: None,
+        }
+    }
+}
+
+// all the transactions this chain has seen, indexed by status so that they can be listed page by page
+pub struct TxIndexes<'a> {
+    pub status: MultiIndex<'a, String, TxInfo, TxId>,
+}
+
+impl<'a> IndexList<TxInfo> for TxIndexes<'a> {
+    fn get_indexes(&'_ self) -> Box<dyn Iterator<Item = &'_ dyn Index<TxInfo>> + '_> {
+        let v: Vec<&dyn Index<TxInfo>> = vec![&self.status];
+        Box::new(v.into_iter())
+    }
+}
+
+pub fn txs<'a>() -> IndexedMap<'a, TxId, TxInfo, TxIndexes<'a>> {
+    let indexes = TxIndexes {
+        status: MultiIndex::new(|tx: &TxInfo| tx.status.as_str().to_string(), "txs", "txs__status"),
+    };
+    IndexedMap::new("txs", indexes)
+}
+
+// the payload of every transaction this chain takes part in
+pub const TX_PAYLOADS: Map<TxId, Transaction> = Map::new("tx_payloads");
+
+// content addressed mode, the transaction each digest was announced for
+pub const TX_DIGESTS: Map<&[u8], TxId> = Map::new("tx_digests");
+
+// the sequence number of the last transaction submitted on this chain
+pub const TX_COUNTER: Item<u32> = Item::new("tx_counter");
+
+// saga mode, the progress of a step of the transaction
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum StepStatus{
+    Running,
+    Done,
+    Failed,
+    Compensating,
+    Compensated,
+    // the step could not be undone and stays applied
+    CompensationFailed,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct StepInfo{
+    // the chain running the step
+    pub chain_id: u32,
+    pub status: StepStatus,
+    pub error: Option<String>,
+}
+
+// the steps of a saga that have been started, keyed by their index in the msgs of the payload;
+// the origin tracks every step, the other chains only the ones they run
+pub const SAGA_STEPS: Map<(TxId, u32), StepInfo> = Map::new("saga_steps");
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct SagaReply{
+    pub tx_id: TxId,
+    pub step: u32,
+    pub compensation: bool,
+}
+
+// the key is the id of a submessage running a step of a saga, or its compensation
+pub const SAGA_REPLIES: Map<u64, SagaReply> = Map::new("saga_replies");
+
+// the key is the id of a submessage running the local portion of a transaction, while the value is its tx_id
+pub const PENDING_REPLIES: Map<u64, TxId> = Map::new("pending_replies");
+// the same for the submessages undoing the local portion of an aborted transaction
+pub const PENDING_COMPENSATIONS: Map<u64, TxId> = Map::new("pending_compensations");
+pub const REPLY_COUNTER: Item<u64> = Item::new("reply_counter");
+// the transaction whose local portion, compensation or saga step is running, until its reply comes back;
+// the messages it runs are sent by this contract too, so they must not be able to start another one
+pub const LOCAL_RUN: Item<TxId> = Item::new("local_run");
 
-// the start time and commit time represent the time when the transaction is added to submitted to the contract 
-pub const START_TIME: Map<u32, Timestamp> = Map::new("start_time");
-pub const END_TIME: Map<u32, Timestamp> = Map::new("end_time");
+// the key is the tx_id, while the value is the number of distinct chains that voted so far for that transaction
+pub const DIRTY_VOTES_MAP : Map<TxId, u32> = Map::new("dirty_votes_map");
+pub const PREPARE_VOTES_MAP : Map<TxId, u32> = Map::new("prepare_votes_map");
+pub const PRE_COMMIT_VOTES_MAP : Map<TxId, u32> = Map::new("pre_commit_votes_map");
 
-// the first element of the value tuple is the index of transactions, while the second is the number of votes received so far
-// for simplicity, we implement no reentrancy checks for the votes
-pub const DIRTY_VOTES_MAP : Map<u32, u32> = Map::new("dirty_votes_map");
-pub const PREPARE_VOTES_MAP : Map<u32, u32> = Map::new("prepare_votes_map");
+// the key is (tx_id, chain_id of the voter), while the value is the time the vote was counted
+// each chain is counted once per transaction, so replayed votes cannot push a transaction past quorum
+pub const DIRTY_VOTERS: Map<(TxId, u32), Timestamp> = Map::new("dirty_voters");
+pub const PREPARE_VOTERS: Map<(TxId, u32), Timestamp> = Map::new("prepare_voters");
+pub const PRE_COMMIT_VOTERS: Map<(TxId, u32), Timestamp> = Map::new("pre_commit_voters");
 
-pub const COMMITTED_MAP:Map<u32, bool> = Map::new("committed_map");
-pub const ABORTED_MAP:Map<u32, bool> = Map::new("aborted_map");
+// the key is (tx_id, kind of the phase message, chain_id of the peer), while the value is the status the peer reported in its acknowledgement
+pub const CONFIRMATIONS: Map<(TxId, &str, u32), TxStatus> = Map::new("confirmations");
 
+// the key is (tx_id, chain_id of the peer), while the value is the time the peer acknowledged the commit decision;
+// once every peer has, the transaction is finished everywhere and its votes and payload are dropped
+pub const DECISION_ACKS: Map<(TxId, u32), Timestamp> = Map::new("decision_acks");
 
 pub const CHANNELS: Map<u32, String> = Map::new("channels");
-pub const HIGHEST_ABORT: Map<u32, i32> = Map::new("highest_abort");
\ No newline at end of file
+// the other way around, the chain each channel is bound to by its WhoAmI
+pub const CHAINS: Map<&str, u32> = Map::new("chains");
+pub const HIGHEST_ABORT: Map<u32, i32> = Map::new("highest_abort");
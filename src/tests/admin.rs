@@ -0,0 +1,81 @@
+use cosmwasm_std::{Addr, CosmosMsg, IbcMsg, IbcOrder};
+
+use crate::error::ContractError;
+use crate::msg::{ExecuteMsg, QueryMsg};
+use crate::state::{Config, TimeoutPolicy, TxStatus, CHANNELS};
+use crate::tests::{attr, payload, Net, ADMIN, USER};
+
+fn update_config(packet_timeout: Option<TimeoutPolicy>) -> ExecuteMsg {
+    ExecuteMsg::UpdateConfig {
+        channel_order: Some(IbcOrder::Ordered),
+        allowed_ports: Some(vec!["wasm.peer".to_string()]),
+        allowed_connections: None,
+        packet_timeout,
+        content_addressed: None,
+    }
+}
+
+#[test]
+fn only_the_admin_manages_the_contract() {
+    let mut net = Net::new(&[1, 2]);
+    let msgs = [
+        update_config(None),
+        ExecuteMsg::SetValidator { validator: None },
+        ExecuteMsg::TransferAdmin { admin: USER.to_string() },
+        ExecuteMsg::RemovePeer { chain_id: 2 },
+        ExecuteMsg::SetPeerActive { chain_id: 2, active: false },
+    ];
+    for msg in msgs {
+        assert!(matches!(net.execute(1, USER, msg), Err(ContractError::Unauthorized {})));
+    }
+}
+
+#[test]
+fn the_admin_updates_the_config() {
+    let mut net = Net::new(&[1]);
+    net.execute(1, ADMIN, update_config(None)).unwrap();
+    let config: Config = net.query(1, QueryMsg::GetConfig {});
+    assert_eq!(config.channel_order, IbcOrder::Ordered);
+    assert_eq!(config.allowed_ports, vec!["wasm.peer".to_string()]);
+    assert!(config.allowed_connections.is_empty());
+
+    let invalid = TimeoutPolicy { seconds: None, height: None };
+    assert!(matches!(net.execute(1, ADMIN, update_config(Some(invalid))), Err(ContractError::InvalidTimeout {})));
+
+    net.execute(1, ADMIN, ExecuteMsg::TransferAdmin { admin: USER.to_string() }).unwrap();
+    let config: Config = net.query(1, QueryMsg::GetConfig {});
+    assert_eq!(config.admin, Addr::unchecked(USER));
+    assert!(matches!(net.execute(1, ADMIN, update_config(None)), Err(ContractError::Unauthorized {})));
+}
+
+#[test]
+fn the_admin_removes_a_peer_and_closes_its_channel() {
+    let mut net = Net::new(&[1, 2, 3]);
+    let tx_id = net.submit(1, payload());
+    let res = net.execute(1, ADMIN, ExecuteMsg::RemovePeer { chain_id: 3 }).unwrap();
+    assert!(res.messages.iter().any(|msg| msg.msg == CosmosMsg::Ibc(IbcMsg::CloseChannel { channel_id: "channel-3".to_string() })));
+    assert_eq!(attr(&res.attributes, "aborted"), Some("1"));
+    assert_eq!(net.status(1, tx_id), TxStatus::Aborted);
+    assert!(!CHANNELS.has(net.storage(1), 3));
+    assert!(matches!(net.execute(1, ADMIN, ExecuteMsg::RemovePeer { chain_id: 3 }), Err(ContractError::UnknownChain { chain_id: 3 })));
+}
+
+#[test]
+fn a_paused_peer_leaves_the_vote_set_until_it_is_back() {
+    let mut net = Net::new(&[1, 2, 3]);
+    net.execute(1, ADMIN, ExecuteMsg::SetPeerActive { chain_id: 3, active: false }).unwrap();
+    let node_number: u32 = net.query(1, QueryMsg::GetNodeNumber {});
+    assert_eq!(node_number, 2);
+    // the channel is kept, but the peer cannot take part in a transaction meanwhile
+    assert!(CHANNELS.has(net.storage(1), 3));
+    let mut tx = payload();
+    tx.participants = vec![3];
+    assert!(matches!(net.execute(1, USER, ExecuteMsg::Input { value: tx }), Err(ContractError::InactivePeer { chain_id: 3 })));
+
+    net.execute(1, ADMIN, ExecuteMsg::SetPeerActive { chain_id: 3, active: true }).unwrap();
+    let node_number: u32 = net.query(1, QueryMsg::GetNodeNumber {});
+    assert_eq!(node_number, 3);
+    let tx_id = net.submit(1, payload());
+    net.run();
+    assert_eq!(net.status(3, tx_id), TxStatus::Committed);
+}
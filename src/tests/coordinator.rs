@@ -0,0 +1,50 @@
+use cosmwasm_std::ContractResult;
+
+use crate::msg::Op;
+use crate::state::{Protocol, TxStatus};
+use crate::tests::{attr, payload, Net, Packet};
+
+fn coordinated(chain_ids: &[u32]) -> Net {
+    Net::with(chain_ids, |msg| msg.protocol = Some(Protocol::Coordinator))
+}
+
+#[test]
+fn the_origin_coordinates_the_transaction() {
+    let mut net = coordinated(&[1, 2, 3]);
+    let tx_id = net.submit(1, payload());
+    assert!(net.queue.iter().all(|packet| matches!(packet.op(), Op::Propose { .. })));
+
+    // the participants only ever talk to the coordinator
+    while let Some(packet) = net.queue.pop_front() {
+        assert!(packet.from == 1 || packet.to == 1, "{:?}", packet.op());
+        net.relay(packet);
+    }
+    for chain_id in [1, 2, 3] {
+        assert_eq!(net.status(chain_id, tx_id), TxStatus::Committed, "chain {}", chain_id);
+    }
+}
+
+#[test]
+fn a_participant_only_takes_the_decision_of_the_coordinator() {
+    let mut net = coordinated(&[1, 2, 3]);
+    let tx_id = net.submit(1, payload());
+    net.run_only(|packet| matches!(packet.op(), Op::Propose { .. }));
+
+    let ack = net.relay(Packet::new(3, 2, Op::Commit { value: tx_id }));
+    assert_eq!(ack, ContractResult::Err("invalid packet: only the origin of transaction 1-1 can send this message, not chain 3".to_string()));
+    assert_eq!(net.status(2, tx_id), TxStatus::Dirty);
+}
+
+#[test]
+fn a_participant_only_gives_up_when_the_coordinator_is_lost() {
+    let mut net = coordinated(&[1, 2, 3]);
+    let tx_id = net.submit(1, payload());
+    net.run_only(|packet| matches!(packet.op(), Op::Propose { .. }));
+
+    let res = net.close(2, 3);
+    assert_eq!(attr(&res.attributes, "aborted"), Some("0"));
+    assert_eq!(net.status(2, tx_id), TxStatus::Dirty);
+    let res = net.close(2, 1);
+    assert_eq!(attr(&res.attributes, "aborted"), Some("1"));
+    assert_eq!(net.status(2, tx_id), TxStatus::Aborted);
+}
@@ -0,0 +1,67 @@
+use cosmwasm_std::{ContractResult, Order};
+
+use crate::msg::{ExecuteMsg, Op};
+use crate::state::{ChainMsgs, TxStatus, DECISION_ACKS, TX_PAYLOADS};
+use crate::tests::{broken, payload, send, transfer, Net, Packet, ADMIN};
+
+#[test]
+fn every_chain_prunes_the_transaction_once_the_decision_is_acknowledged() {
+    let mut net = Net::new(&[1, 2, 3]);
+    let tx_id = net.submit(1, payload());
+    net.run();
+
+    // only the record of the transaction is left
+    for chain_id in [1, 2, 3] {
+        assert_eq!(net.status(chain_id, tx_id), TxStatus::Committed, "chain {}", chain_id);
+        assert!(!TX_PAYLOADS.has(net.storage(chain_id), tx_id), "chain {}", chain_id);
+        assert_eq!(DECISION_ACKS.prefix(tx_id).range(net.storage(chain_id), None, None, Order::Ascending).count(), 0);
+    }
+}
+
+#[test]
+fn a_chain_that_missed_the_prepare_votes_learns_the_decision() {
+    let mut net = Net::new(&[1, 2, 3]);
+    let tx_id = net.submit(1, payload());
+    let missed = |packet: &Packet| packet.to == 3 && matches!(packet.op(), Op::PrepareSuccess { .. });
+    net.run_only(|packet| !missed(packet));
+    assert!(!net.take(missed).is_empty());
+    net.run();
+
+    // the commit of its peers settles the transaction on the lagging chain, which ran its portion with them
+    assert_eq!(net.status(3, tx_id), TxStatus::Committed);
+    assert!(!TX_PAYLOADS.has(net.storage(3), tx_id));
+}
+
+#[test]
+fn a_decision_ack_for_an_undecided_transaction_is_refused() {
+    let mut net = Net::new(&[1, 2]);
+    let tx_id = net.submit(1, payload());
+
+    let ack = net.relay(Packet::new(2, 1, Op::DecisionAck { value: tx_id }));
+    assert_eq!(ack, ContractResult::Err("invalid packet: transaction 1-1 is not committed on this chain".to_string()));
+    assert_eq!(net.status(1, tx_id), TxStatus::Dirty);
+}
+
+#[test]
+fn an_aborted_transaction_is_pruned_once_its_compensation_ran() {
+    let mut net = Net::new(&[1, 2]);
+    net.set_validator(2, |_| Some("paused".to_string()));
+    let refused = net.submit(1, payload());
+    net.run();
+    for chain_id in [1, 2] {
+        assert_eq!(net.status(chain_id, refused), TxStatus::Aborted);
+        assert!(!TX_PAYLOADS.has(net.storage(chain_id), refused), "chain {}", chain_id);
+    }
+
+    // the refund of a portion that already ran still needs the payload, and its reply drops it
+    let mut tx = payload();
+    tx.msgs = vec![transfer(1, 10), ChainMsgs { chain_id: 2, msgs: vec![broken()], compensation: vec![] }];
+    let tx_id = net.submit(1, tx);
+    net.execute(2, ADMIN, ExecuteMsg::SetValidator { validator: None }).unwrap();
+    net.run();
+    assert_eq!(net.executed, vec![(1, send("alice", 10)), (1, send("refund", 10))]);
+    for chain_id in [1, 2] {
+        assert_eq!(net.status(chain_id, tx_id), TxStatus::Aborted);
+        assert!(!TX_PAYLOADS.has(net.storage(chain_id), tx_id), "chain {}", chain_id);
+    }
+}
@@ -0,0 +1,58 @@
+use cosmwasm_std::{from_json, to_json_binary, ContractResult, HexBinary};
+
+use crate::contract::query;
+use crate::msg::{GetTxResponse, Op, PacketData, QueryMsg};
+use crate::state::TxStatus;
+use crate::tests::{payload, Net, Packet};
+
+fn content_addressed(chain_ids: &[u32]) -> Net {
+    Net::with(chain_ids, |msg| msg.content_addressed = Some(true))
+}
+
+// the packet with its digest replaced
+fn with_digest(packet: Packet, digest: Option<HexBinary>) -> Packet {
+    let mut data: PacketData = from_json(&packet.data).unwrap();
+    data.digest = digest;
+    Packet { data: to_json_binary(&data).unwrap(), ..packet }
+}
+
+#[test]
+fn every_chain_knows_the_transaction_by_its_digest() {
+    let mut net = content_addressed(&[1, 2, 3]);
+    let tx_id = net.submit(1, payload());
+    net.run();
+
+    let digest = net.tx(1, tx_id).digest.unwrap();
+    for chain_id in [1, 2, 3] {
+        let tx: GetTxResponse = net.query(chain_id, QueryMsg::GetTxByDigest { digest: digest.clone() });
+        assert_eq!((tx.tx_id, tx.status), (tx_id, TxStatus::Committed));
+        assert_eq!(tx.digest, Some(digest.clone()));
+    }
+    let chain = net.chain(1);
+    assert!(query(chain.deps.as_ref(), chain.env.clone(), QueryMsg::GetTxByDigest { digest: HexBinary::from(&[0; 32]) }).is_err());
+}
+
+#[test]
+fn an_announcement_has_to_match_its_digest() {
+    let mut net = content_addressed(&[1, 2]);
+    let tx_id = net.submit(1, payload());
+    let announcement = net.queue.pop_front().unwrap();
+
+    let invalid = "invalid packet: transaction 1-1 does not match the digest it was announced with".to_string();
+    let ack = net.relay(with_digest(announcement.clone(), Some(HexBinary::from(&[0; 32]))));
+    assert_eq!(ack, ContractResult::Err(invalid.clone()));
+    // a content addressed chain does not take a transaction without a digest either
+    let ack = net.relay(with_digest(announcement, None));
+    assert_eq!(ack, ContractResult::Err(invalid));
+    // the refusal aborts the transaction at its origin
+    assert_eq!(net.status(1, tx_id), TxStatus::Aborted);
+}
+
+#[test]
+fn a_vote_has_to_carry_the_digest_of_the_transaction() {
+    let mut net = content_addressed(&[1, 2]);
+    let tx_id = net.submit(1, payload());
+    let ack = net.relay(Packet::new(2, 1, Op::DirtySuccess { value: tx_id, tx: None }));
+    assert_eq!(ack, ContractResult::Err("invalid packet: transaction 1-1 does not match the digest it was announced with".to_string()));
+    assert_eq!(net.tx(1, tx_id).dirty_voters, vec![1]);
+}
@@ -0,0 +1,83 @@
+use cosmwasm_std::testing::MOCK_CONTRACT_ADDR;
+use cosmwasm_std::{to_json_binary, WasmMsg};
+
+use crate::error::ContractError;
+use crate::msg::ExecuteMsg;
+use crate::state::{ChainMsgs, Expiration, TxStatus, LOCAL_RUN};
+use crate::tests::{broken, payload, send, transfer, Net, USER};
+
+#[test]
+fn every_chain_runs_its_own_portion() {
+    let mut net = Net::new(&[1, 2, 3]);
+    let mut tx = payload();
+    tx.msgs = vec![transfer(1, 10), transfer(2, 20), ChainMsgs { chain_id: 2, msgs: vec![send("bob", 5)], compensation: vec![] }];
+    let tx_id = net.submit(1, tx);
+    net.run();
+
+    for chain_id in [1, 2, 3] {
+        assert_eq!(net.status(chain_id, tx_id), TxStatus::Committed);
+    }
+    assert_eq!(net.executed, vec![(1, send("alice", 10)), (2, send("alice", 20)), (2, send("bob", 5))]);
+    assert!(net.tx(1, tx_id).executed_at.is_some());
+    // a chain without any portion has nothing to run
+    assert!(net.tx(3, tx_id).executed_at.is_none());
+}
+
+#[test]
+fn a_portion_that_fails_aborts_the_transaction_everywhere() {
+    let mut net = Net::new(&[1, 2]);
+    let mut tx = payload();
+    tx.msgs = vec![transfer(1, 10), ChainMsgs { chain_id: 2, msgs: vec![send("bob", 5), broken()], compensation: vec![] }];
+    let tx_id = net.submit(1, tx);
+    net.run();
+
+    assert_eq!(net.status(1, tx_id), TxStatus::Aborted);
+    let tx = net.tx(2, tx_id);
+    assert_eq!(tx.status, TxStatus::Aborted);
+    assert!(tx.error.unwrap().contains("application failed"));
+    assert!(tx.executed_at.is_none());
+    // the failed portion is rolled back as a whole, while the one that ran is undone
+    assert_eq!(net.executed, vec![(1, send("alice", 10)), (1, send("refund", 10))]);
+}
+
+#[test]
+fn only_the_contract_runs_a_portion_and_one_at_a_time() {
+    let mut net = Net::new(&[1, 2]);
+    let tx_id = net.submit(1, payload());
+    let msg = ExecuteMsg::ExecuteLocal { tx_id, compensation: false };
+    assert!(matches!(net.execute(1, USER, msg.clone()), Err(ContractError::Unauthorized {})));
+
+    // a message of the payload calling back into the contract is sent by the contract too, and is refused
+    let mut tx = payload();
+    let reentry = WasmMsg::Execute { contract_addr: MOCK_CONTRACT_ADDR.to_string(), msg: to_json_binary(&msg).unwrap(), funds: vec![] };
+    tx.msgs = vec![ChainMsgs { chain_id: 2, msgs: vec![reentry.into()], compensation: vec![] }];
+    let other = net.submit(1, tx);
+    net.run();
+    let tx = net.tx(2, other);
+    assert_eq!(tx.status, TxStatus::Aborted);
+    assert!(tx.error.unwrap().contains("unauthorized"));
+    assert_eq!(net.status(1, other), TxStatus::Aborted);
+    assert_eq!(LOCAL_RUN.may_load(net.storage(2)).unwrap(), None);
+}
+
+#[test]
+fn a_portion_that_outlives_its_transaction_is_undone_at_once() {
+    let mut net = Net::new(&[1, 2]);
+    let start = net.chain(1).env.block.time;
+    let crank = WasmMsg::Execute { contract_addr: MOCK_CONTRACT_ADDR.to_string(), msg: to_json_binary(&ExecuteMsg::Crank { limit: None }).unwrap(), funds: vec![] };
+    let mut tx = payload();
+    tx.expires_at = Some(Expiration::AtTime(start.plus_seconds(10)));
+    tx.msgs = vec![ChainMsgs { chain_id: 1, msgs: vec![crank.into(), send("alice", 10)], compensation: vec![send("refund", 10)] }];
+    let tx_id = net.submit(1, tx);
+    net.run_only(|packet| packet.from == 1);
+
+    // the portion runs once the transaction has expired, and the crank it carries aborts the transaction meanwhile
+    net.advance(20);
+    net.run();
+    let tx = net.tx(1, tx_id);
+    assert_eq!(tx.status, TxStatus::Aborted);
+    assert!(tx.executed_at.is_some());
+    assert_eq!(tx.error, None);
+    assert_eq!(net.executed, vec![(1, send("alice", 10)), (1, send("refund", 10))]);
+    assert_eq!(net.status(2, tx_id), TxStatus::Aborted);
+}
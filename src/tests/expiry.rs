@@ -0,0 +1,51 @@
+use crate::msg::{ExecuteMsg, Op};
+use crate::state::{Expiration, TxStatus};
+use crate::tests::{attr, payload, Net, USER};
+
+#[test]
+fn the_crank_aborts_the_expired_transactions_a_few_at_a_time() {
+    let mut net = Net::new(&[1, 2]);
+    let start = net.chain(1).env.block.clone();
+    let mut expiring = payload();
+    expiring.expires_at = Some(Expiration::AtTime(start.time.plus_seconds(10)));
+    let first = net.submit(1, expiring.clone());
+    expiring.expires_at = Some(Expiration::AtHeight(start.height + 2));
+    let second = net.submit(1, expiring);
+    let lasting = net.submit(1, payload());
+
+    // nothing is expired yet
+    let res = net.execute(1, USER, ExecuteMsg::Crank { limit: None }).unwrap();
+    assert_eq!(attr(&res.attributes, "aborted"), Some("0"));
+
+    net.advance(10);
+    let res = net.execute(1, USER, ExecuteMsg::Crank { limit: Some(1) }).unwrap();
+    assert_eq!(attr(&res.attributes, "aborted"), Some("1"));
+    assert_eq!(net.status(1, first), TxStatus::Aborted);
+    assert_eq!(net.status(1, second), TxStatus::Dirty);
+    let res = net.execute(1, USER, ExecuteMsg::Crank { limit: Some(1) }).unwrap();
+    assert_eq!(attr(&res.attributes, "aborted"), Some("1"));
+
+    // the peers learn about the abortions, and the transaction without a deadline goes on
+    net.run();
+    for tx_id in [first, second] {
+        assert_eq!(net.status(2, tx_id), TxStatus::Aborted);
+    }
+    assert_eq!(net.status(2, lasting), TxStatus::Committed);
+}
+
+#[test]
+fn the_crank_leaves_a_transaction_this_chain_voted_to_prepare() {
+    let mut net = Net::new(&[1, 2]);
+    let mut tx = payload();
+    tx.expires_at = Some(Expiration::AtTime(net.chain(1).env.block.time.plus_seconds(10)));
+    let tx_id = net.submit(1, tx);
+    net.run_only(|packet| !(packet.to == 1 && matches!(packet.op(), Op::PrepareSuccess { .. } | Op::Commit { .. })));
+    assert_eq!(net.status(1, tx_id), TxStatus::Prepared);
+
+    // its peer may have committed already, so only the decision settles it
+    net.advance(10);
+    let res = net.execute(1, USER, ExecuteMsg::Crank { limit: None }).unwrap();
+    assert_eq!(attr(&res.attributes, "aborted"), Some("0"));
+    net.run();
+    assert_eq!(net.status(1, tx_id), TxStatus::Committed);
+}
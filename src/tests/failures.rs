@@ -0,0 +1,68 @@
+use cosmwasm_std::{to_json_binary, Binary, ContractResult};
+
+use crate::msg::Op;
+use crate::state::TxStatus;
+use crate::tests::{payload, Net};
+
+#[test]
+fn a_dirty_vote_that_times_out_aborts_the_transaction() {
+    let mut net = Net::new(&[1, 2]);
+    let tx_id = net.submit(1, payload());
+    let packet = net.take(|packet| matches!(packet.op(), Op::DirtySuccess { .. })).remove(0);
+    net.time_out(packet);
+    assert_eq!(net.status(1, tx_id), TxStatus::Aborted);
+
+    net.run();
+    assert_eq!(net.status(2, tx_id), TxStatus::Aborted);
+}
+
+#[test]
+fn a_prepare_vote_that_times_out_asks_for_the_decision() {
+    let mut net = Net::new(&[1, 2]);
+    let tx_id = net.submit(1, payload());
+    net.run_only(|packet| packet.from == 1);
+    assert_eq!(net.status(2, tx_id), TxStatus::Prepared);
+
+    // the origin may commit with the votes it has, so the peer that voted to prepare cannot abort on its own
+    let packet = net.take(|packet| matches!(packet.op(), Op::PrepareSuccess { .. })).remove(0);
+    net.time_out(packet);
+    assert_eq!(net.status(2, tx_id), TxStatus::Prepared);
+    assert!(net.queue.iter().any(|packet| packet.from == 2 && packet.op() == Op::QueryDecision { value: tx_id }));
+
+    net.run();
+    assert_eq!(net.status(1, tx_id), TxStatus::Committed);
+    assert_eq!(net.status(2, tx_id), TxStatus::Committed);
+}
+
+fn refusal() -> Binary {
+    to_json_binary(&ContractResult::<()>::Err("refused".to_string())).unwrap()
+}
+
+#[test]
+fn a_refused_announcement_aborts_the_transaction() {
+    let mut net = Net::new(&[1, 2, 3]);
+    let tx_id = net.submit(1, payload());
+    let packet = net.take(|packet| packet.to == 2).remove(0);
+    net.acknowledge(packet, refusal());
+    assert_eq!(net.status(1, tx_id), TxStatus::Aborted);
+    assert!(net.tx(1, tx_id).confirmations.is_empty());
+
+    net.run();
+    assert_eq!(net.status(3, tx_id), TxStatus::Aborted);
+}
+
+#[test]
+fn a_refused_prepare_vote_asks_for_the_decision() {
+    let mut net = Net::new(&[1, 2]);
+    let tx_id = net.submit(1, payload());
+    net.run_only(|packet| packet.from == 1);
+    let packet = net.take(|packet| matches!(packet.op(), Op::PrepareSuccess { .. })).remove(0);
+    net.acknowledge(packet, refusal());
+    assert_eq!(net.status(2, tx_id), TxStatus::Prepared);
+
+    // a refused query is left to the next Resolve
+    let query = net.take(|packet| matches!(packet.op(), Op::QueryDecision { .. })).remove(0);
+    net.acknowledge(query, refusal());
+    assert_eq!(net.status(2, tx_id), TxStatus::Prepared);
+    assert!(net.queue.iter().all(|packet| !matches!(packet.op(), Op::QueryDecision { .. } | Op::Abortion { .. })));
+}
@@ -0,0 +1,61 @@
+use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_ibc_channel_connect_ack, mock_ibc_channel_open_init, mock_ibc_channel_open_try, mock_info};
+use cosmwasm_std::{Ibc3ChannelOpenResponse, IbcOrder};
+
+use crate::contract::instantiate;
+use crate::error::ContractError;
+use crate::ibc::{ibc_channel_connect, ibc_channel_open, IBC_VERSION};
+use crate::msg::InstantiateMsg;
+use crate::tests::{instantiate_msg, MockDeps, ADMIN};
+
+fn setup(config: impl Fn(&mut InstantiateMsg)) -> MockDeps {
+    let mut deps = mock_dependencies();
+    let mut msg = instantiate_msg(1);
+    config(&mut msg);
+    instantiate(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), msg).unwrap();
+    deps
+}
+
+#[test]
+fn a_channel_speaks_the_version_of_the_contract() {
+    let mut deps = setup(|_| {});
+    let res = ibc_channel_open(deps.as_mut(), mock_env(), mock_ibc_channel_open_init("channel-2", IbcOrder::Unordered, IBC_VERSION)).unwrap();
+    assert_eq!(res, Some(Ibc3ChannelOpenResponse { version: IBC_VERSION.to_string() }));
+    // the relayer may leave the version to the contract
+    let res = ibc_channel_open(deps.as_mut(), mock_env(), mock_ibc_channel_open_init("channel-2", IbcOrder::Unordered, "")).unwrap();
+    assert_eq!(res, Some(Ibc3ChannelOpenResponse { version: IBC_VERSION.to_string() }));
+
+    let err = ibc_channel_open(deps.as_mut(), mock_env(), mock_ibc_channel_open_init("channel-2", IbcOrder::Unordered, "avalon-1")).unwrap_err();
+    assert!(matches!(err, ContractError::InvalidIbcVersion { .. }));
+    let err = ibc_channel_open(deps.as_mut(), mock_env(), mock_ibc_channel_open_try("channel-2", IbcOrder::Unordered, "avalon-1")).unwrap_err();
+    assert!(matches!(err, ContractError::InvalidIbcVersion { .. }));
+    let err = ibc_channel_connect(deps.as_mut(), mock_env(), mock_ibc_channel_connect_ack("channel-2", IbcOrder::Unordered, "avalon-1")).unwrap_err();
+    assert!(matches!(err, ContractError::InvalidIbcVersion { .. }));
+}
+
+#[test]
+fn a_channel_has_the_order_of_the_config() {
+    let mut deps = setup(|_| {});
+    let err = ibc_channel_open(deps.as_mut(), mock_env(), mock_ibc_channel_open_init("channel-2", IbcOrder::Ordered, IBC_VERSION)).unwrap_err();
+    assert!(matches!(err, ContractError::InvalidIbcOrder { expected: IbcOrder::Unordered, actual: IbcOrder::Ordered }));
+
+    let mut deps = setup(|msg| msg.channel_order = Some(IbcOrder::Ordered));
+    assert!(ibc_channel_open(deps.as_mut(), mock_env(), mock_ibc_channel_open_init("channel-2", IbcOrder::Ordered, IBC_VERSION)).is_ok());
+}
+
+#[test]
+fn only_the_allowed_counterparties_open_a_channel() {
+    // the mock channel leads to port their_port over connection-2
+    let mut deps = setup(|msg| msg.allowed_ports = Some(vec!["other_port".to_string()]));
+    let err = ibc_channel_open(deps.as_mut(), mock_env(), mock_ibc_channel_open_init("channel-2", IbcOrder::Unordered, IBC_VERSION)).unwrap_err();
+    assert!(matches!(err, ContractError::UnauthorizedPort { port_id } if port_id == "their_port"));
+
+    let mut deps = setup(|msg| msg.allowed_connections = Some(vec!["connection-0".to_string()]));
+    let err = ibc_channel_open(deps.as_mut(), mock_env(), mock_ibc_channel_open_init("channel-2", IbcOrder::Unordered, IBC_VERSION)).unwrap_err();
+    assert!(matches!(err, ContractError::UnauthorizedConnection { connection_id } if connection_id == "connection-2"));
+
+    let mut deps = setup(|msg| {
+        msg.allowed_ports = Some(vec!["their_port".to_string()]);
+        msg.allowed_connections = Some(vec!["connection-2".to_string()]);
+    });
+    assert!(ibc_channel_open(deps.as_mut(), mock_env(), mock_ibc_channel_open_init("channel-2", IbcOrder::Unordered, IBC_VERSION)).is_ok());
+}
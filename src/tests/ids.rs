@@ -0,0 +1,42 @@
+use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_info};
+
+use crate::contract::instantiate;
+use crate::error::ContractError;
+use crate::msg::{ExecuteMsg, ListTxsResponse, QueryMsg};
+use crate::state::{TxId, TxStatus, LEGACY_ORIGIN, TX_COUNTER};
+use crate::tests::{attr, instantiate_msg, payload, Net, ADMIN, USER};
+
+#[test]
+fn chains_submitting_at_once_never_share_an_id() {
+    let mut net = Net::new(&[1, 2]);
+    let res = net.execute(1, USER, ExecuteMsg::Input { value: payload() }).unwrap();
+    assert_eq!(attr(&res.attributes, "tx_id"), Some("1-1"));
+    let ours = TxId { origin: 1, seq: 1 };
+    let theirs = net.submit(2, payload());
+    assert_eq!(theirs, TxId { origin: 2, seq: 1 });
+    let next = net.submit(1, payload());
+    assert_eq!(next, TxId { origin: 1, seq: 2 });
+    net.run();
+
+    // each transaction is settled on its own, with no vote taken for the other
+    for chain_id in [1, 2] {
+        let list: ListTxsResponse = net.query(chain_id, QueryMsg::ListTxs { status: Some(TxStatus::Committed), start_after: None, limit: None });
+        let ids: Vec<TxId> = list.txs.iter().map(|tx| tx.tx_id).collect();
+        assert_eq!(ids, vec![ours, next, theirs]);
+    }
+}
+
+#[test]
+fn an_id_is_never_given_twice() {
+    let mut net = Net::new(&[1, 2]);
+    let tx_id = net.submit(1, payload());
+    TX_COUNTER.save(net.storage(1), &0).unwrap();
+    let err = net.execute(1, USER, ExecuteMsg::Input { value: payload() }).unwrap_err();
+    assert!(matches!(err, ContractError::TxExists { tx_id: existing } if existing == tx_id));
+}
+
+#[test]
+fn the_origin_of_the_legacy_transactions_is_reserved() {
+    let err = instantiate(mock_dependencies().as_mut(), mock_env(), mock_info(ADMIN, &[]), instantiate_msg(LEGACY_ORIGIN)).unwrap_err();
+    assert!(matches!(err, ContractError::ReservedChainId { chain_id: LEGACY_ORIGIN }));
+}
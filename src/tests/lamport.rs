@@ -0,0 +1,32 @@
+use crate::msg::{Op, QueryMsg};
+use crate::state::{TxId, STATE};
+use crate::tests::{payload, Net};
+
+#[test]
+fn a_chain_moves_its_clock_past_the_packets_it_receives() {
+    let mut net = Net::new(&[1, 2]);
+    for _ in 0..3 {
+        net.submit(1, payload());
+    }
+    let packet = net.queue.pop_back().unwrap();
+    assert!(packet.lamport() > STATE.load(net.storage(2)).unwrap().lamport);
+
+    let sent = packet.lamport();
+    net.relay(packet);
+    assert!(STATE.load(net.storage(2)).unwrap().lamport > sent);
+}
+
+#[test]
+fn every_chain_queues_the_transactions_in_the_same_order() {
+    let mut net = Net::new(&[1, 2, 3]);
+    let second = net.submit(2, payload());
+    let third = net.submit(2, payload());
+    let first = net.submit(1, payload());
+
+    // chain 3 hears about the transactions of chain 2 first, and the order comes from the clocks rather than the arrival
+    net.run_only(|packet| matches!(packet.op(), Op::DirtySuccess { tx: Some(_), .. }));
+    for chain_id in [1, 2, 3] {
+        let queue: Vec<TxId> = net.query(chain_id, QueryMsg::GetDirtyTx {});
+        assert_eq!(queue, vec![first, second, third], "chain {}", chain_id);
+    }
+}
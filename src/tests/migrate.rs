@@ -0,0 +1,89 @@
+use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_ibc_packet_ack, mock_ibc_packet_timeout};
+use cosmwasm_std::{to_json_binary, IbcAcknowledgement, Timestamp};
+use cw2::set_contract_version;
+use serde_json::json;
+
+use crate::contract::migrate;
+use crate::error::ContractError;
+use crate::ibc::{ibc_packet_ack, ibc_packet_timeout};
+use crate::msg::MigrateMsg;
+use crate::state::{txs, LegacyState, TxId, TxInfo, TxStatus, DIRTY_VOTERS, LEGACY_ORIGIN, LEGACY_STATE, STATE};
+use crate::tests::{attr, Net};
+
+const CONTRACT_NAME: &str = "crates.io:simple-storage";
+
+#[test]
+fn migration_aborts_the_legacy_transactions_in_flight() {
+    let mut deps = mock_dependencies();
+    set_contract_version(deps.as_mut().storage, CONTRACT_NAME, "0.0.1").unwrap();
+    let legacy = LegacyState {
+        node_number: 3,
+        chain_id: 1,
+        channel_ids: vec!["channel-0".to_string(), "channel-1".to_string()],
+        dirty_tx_queue: vec![7],
+        prepare_tx_queue: vec![5],
+        commit_tx_queue: vec![2],
+        dirty_votes: 1,
+        prepare_votes: 1,
+        start_time: Timestamp::from_seconds(10),
+        end_time: None,
+        committed: false,
+        aborted: false,
+        time: 0,
+    };
+    LEGACY_STATE.save(deps.as_mut().storage, &legacy).unwrap();
+
+    let env = mock_env();
+    let res = migrate(deps.as_mut(), env.clone(), MigrateMsg { admin: Some("admin".to_string()) }).unwrap();
+    assert_eq!(attr(&res.attributes, "migrated_txs"), Some("3"));
+
+    let state = STATE.load(&deps.storage).unwrap();
+    assert_eq!(state.chain_id, 1);
+    assert_eq!(state.node_number, 3);
+    assert!(state.dirty_tx_queue.is_empty());
+    assert!(state.prepare_tx_queue.is_empty());
+
+    // every chain files the legacy ids under the same origin, and only dates the abortion the migration performs
+    let load = |seq| -> TxInfo { txs().load(&deps.storage, TxId { origin: LEGACY_ORIGIN, seq }).unwrap() };
+    assert_eq!(load(2).status, TxStatus::Committed);
+    assert_eq!(load(2).committed_at, None);
+    assert_eq!(load(5).status, TxStatus::Aborted);
+    assert_eq!(load(7).status, TxStatus::Aborted);
+    assert_eq!(load(7).aborted_at, Some(env.block.time));
+    assert!(load(7).error.is_some());
+    assert!(!DIRTY_VOTERS.has(&deps.storage, (TxId { origin: LEGACY_ORIGIN, seq: 7 }, 1)));
+}
+
+#[test]
+fn migration_only_moves_forward_from_this_contract() {
+    let mut deps = mock_dependencies();
+    set_contract_version(deps.as_mut().storage, "crates.io:other", "0.0.1").unwrap();
+    let err = migrate(deps.as_mut(), mock_env(), MigrateMsg { admin: None }).unwrap_err();
+    assert!(matches!(err, ContractError::WrongContract { .. }));
+
+    set_contract_version(deps.as_mut().storage, CONTRACT_NAME, "99.0.0").unwrap();
+    let err = migrate(deps.as_mut(), mock_env(), MigrateMsg { admin: None }).unwrap_err();
+    assert!(matches!(err, ContractError::CannotDowngrade { .. }));
+
+    // a contract predating the config needs an admin
+    set_contract_version(deps.as_mut().storage, CONTRACT_NAME, "0.0.1").unwrap();
+    let err = migrate(deps.as_mut(), mock_env(), MigrateMsg { admin: None }).unwrap_err();
+    assert!(matches!(err, ContractError::MissingAdmin {}));
+}
+
+#[test]
+fn the_packets_sent_before_the_upgrade_are_cleared() {
+    let mut net = Net::new(&[1, 2]);
+    let chain = net.chain(1);
+    let legacy = json!({ "DirtySuccess": { "value": 7 } });
+    let ack = IbcAcknowledgement::new(to_json_binary(&json!({ "ok": {} })).unwrap());
+    let msg = mock_ibc_packet_ack("channel-2", &legacy, ack).unwrap();
+    let res = ibc_packet_ack(chain.deps.as_mut(), chain.env.clone(), msg).unwrap();
+    assert_eq!(attr(&res.attributes, "packet"), Some("undecodable"));
+    assert!(res.messages.is_empty());
+
+    let msg = mock_ibc_packet_timeout("channel-2", &legacy).unwrap();
+    let res = ibc_packet_timeout(chain.deps.as_mut(), chain.env.clone(), msg).unwrap();
+    assert_eq!(attr(&res.attributes, "packet"), Some("undecodable"));
+    assert!(res.messages.is_empty());
+}
@@ -0,0 +1,373 @@
+// a handful of chains running the contract, linked two by two by channels that a relayer serves on demand;
+// the messages the contract sends to itself run at once with their replies, and roll back when they fail
+use std::collections::{BTreeMap, VecDeque};
+
+use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_ibc_channel_close_init, mock_ibc_channel_connect_ack, mock_info, MockApi, MockQuerier, MockStorage, MOCK_CONTRACT_ADDR};
+use cosmwasm_std::{coins, from_json, to_json_binary, Addr, Attribute, BankMsg, Binary, ContractResult, CosmosMsg, Env, IbcAcknowledgement, IbcBasicResponse, IbcEndpoint, IbcMsg, IbcOrder, IbcPacket, IbcPacketAckMsg, IbcPacketReceiveMsg, IbcPacketTimeoutMsg, IbcTimeout, Order, OwnedDeps, Reply, ReplyOn, Response, StdError, Storage, SubMsg, SubMsgResponse, SubMsgResult, SystemResult, Timestamp, WasmMsg, WasmQuery};
+
+use crate::contract::{execute, instantiate, query, reply};
+use crate::error::ContractError;
+use crate::ibc::{ibc_channel_close, ibc_channel_connect, ibc_packet_ack, ibc_packet_receive, ibc_packet_timeout, IBC_VERSION};
+use crate::msg::{CanPrepareResponse, ExecuteMsg, GetTxResponse, InstantiateMsg, Op, PacketData, QueryMsg, ValidatorQueryMsg};
+use crate::state::{ChainMsgs, Transaction, TxId, TxStatus};
+
+mod admin;
+mod coordinator;
+mod decision;
+mod digests;
+mod execution;
+mod expiry;
+mod failures;
+mod handshake;
+mod ids;
+mod migrate;
+mod lamport;
+mod participants;
+mod peers;
+mod queries;
+mod saga;
+mod state;
+mod termination;
+mod three_phase;
+mod timeouts;
+mod validator;
+mod votes;
+
+pub const ADMIN: &str = "admin";
+pub const USER: &str = "user";
+pub const VALIDATOR: &str = "validator";
+// every message sent to this contract fails, like an application refusing it
+pub const BROKEN: &str = "broken";
+
+pub type MockDeps = OwnedDeps<MockStorage, MockApi, MockQuerier>;
+
+pub fn instantiate_msg(chain_id: u32) -> InstantiateMsg {
+    InstantiateMsg {
+        chain_id,
+        channel_order: None,
+        allowed_ports: None,
+        allowed_connections: None,
+        validator: None,
+        packet_timeout: None,
+        content_addressed: None,
+        protocol: None,
+        three_phase_timeout: None,
+    }
+}
+
+pub fn payload() -> Transaction {
+    Transaction {
+        participants: vec![],
+        msgs: vec![],
+        expires_at: None,
+        packet_timeout: None,
+        saga: false,
+        lamport: 0,
+    }
+}
+
+// the portion of a transaction run by the chain, undone by a refund
+pub fn transfer(chain_id: u32, amount: u128) -> ChainMsgs {
+    ChainMsgs {
+        chain_id,
+        msgs: vec![send("alice", amount)],
+        compensation: vec![send("refund", amount)],
+    }
+}
+
+pub fn send(to: &str, amount: u128) -> CosmosMsg {
+    CosmosMsg::Bank(BankMsg::Send { to_address: to.to_string(), amount: coins(amount, "atom") })
+}
+
+pub fn broken() -> CosmosMsg {
+    CosmosMsg::Wasm(WasmMsg::Execute { contract_addr: BROKEN.to_string(), msg: Binary::default(), funds: vec![] })
+}
+
+// the channel a chain talks to the given chain on
+pub fn channel(chain_id: u32) -> String {
+    format!("channel-{}", chain_id)
+}
+
+fn chain_of(channel_id: &str) -> u32 {
+    channel_id.trim_start_matches("channel-").parse().unwrap()
+}
+
+pub fn attr<'a>(attributes: &'a [Attribute], key: &str) -> Option<&'a str> {
+    attributes.iter().find(|attr| attr.key == key).map(|attr| attr.value.as_str())
+}
+
+pub fn packet_data(op: Op) -> PacketData {
+    PacketData { lamport: 0, op, digest: None }
+}
+
+#[derive(Clone, Debug)]
+pub struct Packet {
+    pub from: u32,
+    pub to: u32,
+    pub data: Binary,
+}
+
+impl Packet {
+    // a packet as the given chain would send it, stamped with no clock and no digest
+    pub fn new(from: u32, to: u32, op: Op) -> Self {
+        Packet { from, to, data: to_json_binary(&packet_data(op)).unwrap() }
+    }
+
+    pub fn op(&self) -> Op {
+        from_json::<PacketData>(&self.data).unwrap().op
+    }
+
+    pub fn lamport(&self) -> u64 {
+        from_json::<PacketData>(&self.data).unwrap().lamport
+    }
+
+    fn ibc_packet(&self) -> IbcPacket {
+        IbcPacket::new(
+            self.data.clone(),
+            IbcEndpoint { port_id: "wasm".to_string(), channel_id: channel(self.to) },
+            IbcEndpoint { port_id: "wasm".to_string(), channel_id: channel(self.from) },
+            0,
+            IbcTimeout::with_timestamp(Timestamp::from_seconds(0)),
+        )
+    }
+}
+
+pub struct Chain {
+    pub deps: MockDeps,
+    pub env: Env,
+}
+
+pub struct Net {
+    pub chains: BTreeMap<u32, Chain>,
+    // the packets sent and not relayed yet, in the order they were sent
+    pub queue: VecDeque<Packet>,
+    // the application messages that ran, by chain
+    pub executed: Vec<(u32, CosmosMsg)>,
+}
+
+impl Net {
+    pub fn new(chain_ids: &[u32]) -> Self {
+        Net::with(chain_ids, |_| {})
+    }
+
+    // every chain is linked to every other one, and the handshake is relayed
+    pub fn with(chain_ids: &[u32], config: impl Fn(&mut InstantiateMsg)) -> Self {
+        let mut net = Net { chains: BTreeMap::new(), queue: VecDeque::new(), executed: vec![] };
+        for chain_id in chain_ids {
+            let mut deps = mock_dependencies();
+            let env = mock_env();
+            let mut msg = instantiate_msg(*chain_id);
+            config(&mut msg);
+            instantiate(deps.as_mut(), env.clone(), mock_info(ADMIN, &[]), msg).unwrap();
+            net.chains.insert(*chain_id, Chain { deps, env });
+        }
+        for a in chain_ids {
+            for b in chain_ids.iter().filter(|b| *b > a) {
+                net.connect(*a, *b);
+                net.connect(*b, *a);
+            }
+        }
+        net.run();
+        net
+    }
+
+    pub fn connect(&mut self, chain_id: u32, peer: u32) {
+        let chain = self.chain(chain_id);
+        let msg = mock_ibc_channel_connect_ack(&channel(peer), IbcOrder::Unordered, IBC_VERSION);
+        let res = ibc_channel_connect(chain.deps.as_mut(), chain.env.clone(), msg).unwrap();
+        self.dispatch(chain_id, res.messages).unwrap();
+    }
+
+    // the chain closes its channel to the peer, while the peer keeps its own end open
+    pub fn close(&mut self, chain_id: u32, peer: u32) -> IbcBasicResponse {
+        let chain = self.chain(chain_id);
+        let msg = mock_ibc_channel_close_init(&channel(peer), IbcOrder::Unordered, IBC_VERSION);
+        let res = ibc_channel_close(chain.deps.as_mut(), chain.env.clone(), msg).unwrap();
+        self.dispatch(chain_id, res.messages.clone()).unwrap();
+        res
+    }
+
+    pub fn chain(&mut self, chain_id: u32) -> &mut Chain {
+        self.chains.get_mut(&chain_id).unwrap()
+    }
+
+    pub fn storage(&mut self, chain_id: u32) -> &mut MockStorage {
+        &mut self.chain(chain_id).deps.storage
+    }
+
+    pub fn advance(&mut self, seconds: u64) {
+        for chain in self.chains.values_mut() {
+            chain.env.block.time = chain.env.block.time.plus_seconds(seconds);
+            chain.env.block.height += seconds / 5 + 1;
+        }
+    }
+
+    // a top level transaction, rolled back as a whole if it fails
+    pub fn execute(&mut self, chain_id: u32, sender: &str, msg: ExecuteMsg) -> Result<Response, ContractError> {
+        let checkpoint = self.checkpoint(chain_id);
+        let chain = self.chain(chain_id);
+        let result = execute(chain.deps.as_mut(), chain.env.clone(), mock_info(sender, &[]), msg);
+        let res = match result {
+            Ok(res) => res,
+            Err(error) => {
+                self.rollback(chain_id, checkpoint);
+                return Err(error);
+            },
+        };
+        if let Err(error) = self.dispatch(chain_id, res.messages.clone()) {
+            self.rollback(chain_id, checkpoint);
+            return Err(ContractError::Std(StdError::generic_err(error)));
+        }
+        Ok(res)
+    }
+
+    pub fn submit(&mut self, chain_id: u32, tx: Transaction) -> TxId {
+        let res = self.execute(chain_id, USER, ExecuteMsg::Input { value: tx }).unwrap();
+        from_json(res.data.unwrap()).unwrap()
+    }
+
+    pub fn query<T: serde::de::DeserializeOwned>(&mut self, chain_id: u32, msg: QueryMsg) -> T {
+        let chain = self.chain(chain_id);
+        from_json(query(chain.deps.as_ref(), chain.env.clone(), msg).unwrap()).unwrap()
+    }
+
+    pub fn tx(&mut self, chain_id: u32, tx_id: TxId) -> GetTxResponse {
+        self.query(chain_id, QueryMsg::GetTx { tx_id })
+    }
+
+    pub fn status(&mut self, chain_id: u32, tx_id: TxId) -> TxStatus {
+        self.tx(chain_id, tx_id).status
+    }
+
+    // the validator of the chain answers with the given check, which returns the reason of a refusal
+    pub fn set_validator(&mut self, chain_id: u32, check: impl Fn(&Transaction) -> Option<String> + 'static) {
+        self.execute(chain_id, ADMIN, ExecuteMsg::SetValidator { validator: Some(VALIDATOR.to_string()) }).unwrap();
+        self.chain(chain_id).deps.querier.update_wasm(move |query| {
+            let tx = match query {
+                WasmQuery::Smart { msg, .. } => match from_json(msg).unwrap() {
+                    ValidatorQueryMsg::CanPrepare { tx } => tx,
+                },
+                _ => panic!("unexpected query"),
+            };
+            let reason = check(&tx);
+            let response = CanPrepareResponse { can_prepare: reason.is_none(), reason };
+            SystemResult::Ok(ContractResult::Ok(to_json_binary(&response).unwrap()))
+        });
+    }
+
+    // relay every packet, including the ones sent while relaying, until none is left
+    pub fn run(&mut self) {
+        while let Some(packet) = self.queue.pop_front() {
+            self.relay(packet);
+        }
+    }
+
+    // relay only the packets matching the filter, the others wait in the queue
+    pub fn run_only(&mut self, filter: impl Fn(&Packet) -> bool) {
+        while let Some(pos) = self.queue.iter().position(&filter) {
+            let packet = self.queue.remove(pos).unwrap();
+            self.relay(packet);
+        }
+    }
+
+    // take the packets matching the filter out of the queue, so that they are never relayed
+    pub fn take(&mut self, filter: impl Fn(&Packet) -> bool) -> Vec<Packet> {
+        let (taken, kept): (Vec<Packet>, Vec<Packet>) = self.queue.drain(..).partition(filter);
+        self.queue = kept.into();
+        taken
+    }
+
+    pub fn relay(&mut self, packet: Packet) -> ContractResult<serde_json::Value> {
+        let to = self.chain(packet.to);
+        let msg = IbcPacketReceiveMsg::new(packet.ibc_packet(), Addr::unchecked("relayer"));
+        let res = ibc_packet_receive(to.deps.as_mut(), to.env.clone(), msg).unwrap();
+        self.dispatch(packet.to, res.messages).unwrap();
+        self.acknowledge(packet, res.acknowledgement)
+    }
+
+    pub fn acknowledge(&mut self, packet: Packet, ack: Binary) -> ContractResult<serde_json::Value> {
+        // a packet forged by a chain outside of the network has nobody to get its acknowledgement
+        if !self.chains.contains_key(&packet.from) {
+            return from_json(&ack).unwrap();
+        }
+        let from = self.chain(packet.from);
+        let msg = IbcPacketAckMsg::new(IbcAcknowledgement::new(ack.clone()), packet.ibc_packet(), Addr::unchecked("relayer"));
+        let res = ibc_packet_ack(from.deps.as_mut(), from.env.clone(), msg).unwrap();
+        self.dispatch(packet.from, res.messages).unwrap();
+        from_json(&ack).unwrap()
+    }
+
+    pub fn time_out(&mut self, packet: Packet) -> IbcBasicResponse {
+        let from = self.chain(packet.from);
+        let msg = IbcPacketTimeoutMsg::new(packet.ibc_packet(), Addr::unchecked("relayer"));
+        let res = ibc_packet_timeout(from.deps.as_mut(), from.env.clone(), msg).unwrap();
+        self.dispatch(packet.from, res.messages.clone()).unwrap();
+        res
+    }
+
+    // run the messages of a response one after the other, each with its reply, the way the chain does;
+    // a message that fails without a reply fails the whole response
+    pub fn dispatch(&mut self, chain_id: u32, msgs: Vec<SubMsg>) -> Result<(), String> {
+        for msg in msgs {
+            let checkpoint = self.checkpoint(chain_id);
+            let result = self.run_msg(chain_id, msg.msg);
+            if result.is_err() {
+                self.rollback(chain_id, checkpoint);
+            }
+            let result = match (result, msg.reply_on) {
+                (Ok(()), ReplyOn::Always | ReplyOn::Success) => SubMsgResult::Ok(SubMsgResponse { events: vec![], data: None }),
+                (Err(error), ReplyOn::Always | ReplyOn::Error) => SubMsgResult::Err(error),
+                (Ok(()), _) => continue,
+                (Err(error), _) => return Err(error),
+            };
+            let chain = self.chain(chain_id);
+            let res = reply(chain.deps.as_mut(), chain.env.clone(), Reply { id: msg.id, result }).map_err(|error| error.to_string())?;
+            self.dispatch(chain_id, res.messages)?;
+        }
+        Ok(())
+    }
+
+    fn run_msg(&mut self, chain_id: u32, msg: CosmosMsg) -> Result<(), String> {
+        match msg {
+            CosmosMsg::Ibc(IbcMsg::SendPacket { channel_id, data, .. }) => {
+                self.queue.push_back(Packet { from: chain_id, to: chain_of(&channel_id), data });
+                Ok(())
+            },
+            CosmosMsg::Wasm(WasmMsg::Execute { contract_addr, msg, .. }) if contract_addr == MOCK_CONTRACT_ADDR => {
+                let chain = self.chain(chain_id);
+                let msg: ExecuteMsg = from_json(msg).map_err(|error| error.to_string())?;
+                let res = execute(chain.deps.as_mut(), chain.env.clone(), mock_info(MOCK_CONTRACT_ADDR, &[]), msg).map_err(|error| error.to_string())?;
+                self.dispatch(chain_id, res.messages)
+            },
+            CosmosMsg::Wasm(WasmMsg::Execute { contract_addr, .. }) if contract_addr == BROKEN => Err("application failed".to_string()),
+            msg => {
+                self.executed.push((chain_id, msg));
+                Ok(())
+            },
+        }
+    }
+
+    fn checkpoint(&mut self, chain_id: u32) -> Checkpoint {
+        let storage = self.storage(chain_id).range(None, None, Order::Ascending).collect();
+        Checkpoint { storage, queue: self.queue.len(), executed: self.executed.len() }
+    }
+
+    fn rollback(&mut self, chain_id: u32, checkpoint: Checkpoint) {
+        let storage = self.storage(chain_id);
+        let keys: Vec<Vec<u8>> = storage.range(None, None, Order::Ascending).map(|(key, _)| key).collect();
+        for key in keys {
+            storage.remove(&key);
+        }
+        for (key, value) in checkpoint.storage {
+            storage.set(&key, &value);
+        }
+        self.queue.truncate(checkpoint.queue);
+        self.executed.truncate(checkpoint.executed);
+    }
+}
+
+struct Checkpoint {
+    storage: Vec<(Vec<u8>, Vec<u8>)>,
+    queue: usize,
+    executed: usize,
+}
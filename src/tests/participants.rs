@@ -0,0 +1,53 @@
+use crate::error::ContractError;
+use crate::msg::ExecuteMsg;
+use crate::state::{txs, TxStatus};
+use crate::tests::{attr, payload, Net, ADMIN, USER};
+
+#[test]
+fn only_the_participants_hear_about_a_transaction() {
+    let mut net = Net::new(&[1, 2, 3]);
+    let mut tx = payload();
+    tx.participants = vec![2];
+    let tx_id = net.submit(1, tx);
+    assert!(net.queue.iter().all(|packet| packet.to == 2));
+
+    // the origin always takes part, and the quorum is the participants alone
+    let announcement = net.queue.pop_front().unwrap();
+    net.relay(announcement);
+    assert_eq!(net.status(2, tx_id), TxStatus::Prepared);
+    net.run();
+    for chain_id in [1, 2] {
+        assert_eq!(net.status(chain_id, tx_id), TxStatus::Committed);
+    }
+    assert!(!txs().has(net.storage(3), tx_id));
+}
+
+#[test]
+fn the_participants_have_to_be_active_peers() {
+    let mut net = Net::new(&[1, 2, 3]);
+    let mut tx = payload();
+    tx.participants = vec![2, 4];
+    let err = net.execute(1, USER, ExecuteMsg::Input { value: tx.clone() }).unwrap_err();
+    assert!(matches!(err, ContractError::UnknownChain { chain_id: 4 }));
+
+    net.execute(1, ADMIN, ExecuteMsg::SetPeerActive { chain_id: 3, active: false }).unwrap();
+    tx.participants = vec![3];
+    let err = net.execute(1, USER, ExecuteMsg::Input { value: tx }).unwrap_err();
+    assert!(matches!(err, ContractError::InactivePeer { chain_id: 3 }));
+}
+
+#[test]
+fn losing_a_peer_only_aborts_the_transactions_it_takes_part_in() {
+    let mut net = Net::new(&[1, 2, 3]);
+    let mut tx = payload();
+    tx.participants = vec![2];
+    let unrelated = net.submit(1, tx);
+    let involved = net.submit(1, payload());
+
+    let res = net.close(1, 3);
+    assert_eq!(attr(&res.attributes, "aborted"), Some("1"));
+    assert_eq!(net.status(1, involved), TxStatus::Aborted);
+    net.run();
+    assert_eq!(net.status(1, unrelated), TxStatus::Committed);
+    assert_eq!(net.status(2, unrelated), TxStatus::Committed);
+}
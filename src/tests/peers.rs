@@ -0,0 +1,37 @@
+use crate::msg::{Op, QueryMsg};
+use crate::state::{TxStatus, CHAINS, CHANNELS};
+use crate::tests::{attr, payload, Net};
+
+#[test]
+fn closing_a_channel_aborts_the_transactions_waiting_for_the_peer() {
+    let mut net = Net::new(&[1, 2, 3]);
+    let tx_id = net.submit(1, payload());
+    let res = net.close(1, 3);
+    assert_eq!(attr(&res.attributes, "aborted"), Some("1"));
+    assert_eq!(net.status(1, tx_id), TxStatus::Aborted);
+    assert!(!CHANNELS.has(net.storage(1), 3));
+    assert!(!CHAINS.has(net.storage(1), "channel-3"));
+    let node_number: u32 = net.query(1, QueryMsg::GetNodeNumber {});
+    assert_eq!(node_number, 2);
+
+    // the chains that joined meanwhile learn about the abortion, or find their votes refused
+    net.run();
+    for chain_id in [1, 2, 3] {
+        assert_eq!(net.status(chain_id, tx_id), TxStatus::Aborted, "chain {}", chain_id);
+    }
+}
+
+#[test]
+fn the_vote_of_a_closed_peer_is_taken_back() {
+    let mut net = Net::new(&[1, 2, 3]);
+    let tx_id = net.submit(1, payload());
+    net.run_only(|packet| packet.to == 3 || (packet.from == 3 && packet.to == 1 && matches!(packet.op(), Op::DirtySuccess { .. })));
+    assert_eq!(net.tx(1, tx_id).dirty_voters, vec![1, 3]);
+
+    // the transaction does not need the peer anymore, so it goes on without its vote
+    let res = net.close(1, 3);
+    assert_eq!(attr(&res.attributes, "aborted"), Some("0"));
+    let tx = net.tx(1, tx_id);
+    assert_eq!(tx.status, TxStatus::Dirty);
+    assert_eq!((tx.dirty_votes, tx.dirty_voters), (1, vec![1]));
+}
@@ -0,0 +1,75 @@
+use cosmwasm_std::Addr;
+
+use crate::contract::query;
+use crate::msg::{Confirmation, ListTxsResponse, QueryMsg};
+use crate::state::{TxId, TxStatus};
+use crate::tests::{payload, Net, USER};
+
+#[test]
+fn the_record_of_a_transaction_follows_its_lifecycle() {
+    let mut net = Net::new(&[1, 2]);
+    let start = net.chain(1).env.block.time;
+    let tx_id = net.submit(1, payload());
+    let tx = net.tx(1, tx_id);
+    assert_eq!(tx.tx_id, tx_id);
+    assert_eq!(tx.submitter, Some(Addr::unchecked(USER)));
+    assert_eq!((tx.seen_at, tx.submitted_at), (start, Some(start)));
+    assert_eq!(tx.time, None);
+
+    // the announcement is acknowledged with the status the transaction reached on the peer
+    let packet = net.queue.pop_front().unwrap();
+    net.relay(packet);
+    let tx = net.tx(1, tx_id);
+    assert_eq!(tx.confirmations, vec![Confirmation { chain_id: 2, phase: "dirty_success".to_string(), status: TxStatus::Prepared }]);
+    let peer = net.tx(2, tx_id);
+    assert_eq!(peer.submitter, None);
+    assert_eq!(peer.prepare_voters, vec![2]);
+
+    net.advance(10);
+    net.run();
+    let tx = net.tx(1, tx_id);
+    assert_eq!(tx.status, TxStatus::Committed);
+    assert_eq!(tx.committed_at, Some(start.plus_seconds(10)));
+    assert_eq!(tx.time, Some(10));
+}
+
+#[test]
+fn an_unknown_transaction_is_not_found() {
+    let mut net = Net::new(&[1]);
+    let chain = net.chain(1);
+    let tx_id = TxId { origin: 1, seq: 1 };
+    assert!(query(chain.deps.as_ref(), chain.env.clone(), QueryMsg::GetTx { tx_id }).is_err());
+}
+
+fn list(net: &mut Net, status: Option<TxStatus>, start_after: Option<TxId>, limit: Option<u32>) -> Vec<TxId> {
+    let res: ListTxsResponse = net.query(1, QueryMsg::ListTxs { status, start_after, limit });
+    res.txs.into_iter().map(|tx| tx.tx_id).collect()
+}
+
+#[test]
+fn transactions_are_listed_page_by_page() {
+    let mut net = Net::new(&[1, 2]);
+    let committed: Vec<TxId> = (0..3).map(|_| net.submit(1, payload())).collect();
+    net.run();
+    let dirty: Vec<TxId> = (0..2).map(|_| net.submit(1, payload())).collect();
+
+    let first = list(&mut net, None, None, Some(2));
+    assert_eq!(first, committed[..2]);
+    let rest = list(&mut net, None, first.last().copied(), Some(10));
+    assert_eq!(rest, [&committed[2..], &dirty[..]].concat());
+
+    // the status filter pages the same way
+    assert_eq!(list(&mut net, Some(TxStatus::Dirty), None, None), dirty);
+    assert_eq!(list(&mut net, Some(TxStatus::Committed), Some(committed[0]), Some(1)), committed[1..2]);
+    assert!(list(&mut net, Some(TxStatus::Aborted), None, None).is_empty());
+}
+
+#[test]
+fn a_page_holds_at_most_thirty_transactions() {
+    let mut net = Net::new(&[1, 2]);
+    for _ in 0..35 {
+        net.submit(1, payload());
+    }
+    assert_eq!(list(&mut net, None, None, None).len(), 10);
+    assert_eq!(list(&mut net, None, None, Some(100)).len(), 30);
+}
@@ -0,0 +1,43 @@
+use crate::error::ContractError;
+use crate::msg::ExecuteMsg;
+use crate::state::{ChainMsgs, StepStatus, Transaction, TxStatus};
+use crate::tests::{broken, payload, send, transfer, Net, USER};
+
+fn saga(msgs: Vec<ChainMsgs>) -> Transaction {
+    Transaction { saga: true, msgs, ..payload() }
+}
+
+#[test]
+fn a_saga_runs_its_steps_one_after_the_other() {
+    let mut net = Net::new(&[1, 2, 3]);
+    let tx_id = net.submit(1, saga(vec![transfer(3, 10), transfer(2, 20)]));
+    net.run();
+
+    assert_eq!(net.executed, vec![(3, send("alice", 10)), (2, send("alice", 20))]);
+    let tx = net.tx(1, tx_id);
+    assert_eq!(tx.status, TxStatus::Committed);
+    let steps: Vec<(u32, StepStatus)> = tx.steps.iter().map(|step| (step.chain_id, step.status)).collect();
+    assert_eq!(steps, vec![(3, StepStatus::Done), (2, StepStatus::Done)]);
+}
+
+#[test]
+fn a_failed_step_undoes_the_steps_before_it() {
+    let mut net = Net::new(&[1, 2, 3]);
+    let failing = ChainMsgs { chain_id: 3, msgs: vec![broken()], compensation: vec![] };
+    let tx_id = net.submit(1, saga(vec![transfer(2, 10), failing]));
+    net.run();
+
+    assert_eq!(net.executed, vec![(2, send("alice", 10)), (2, send("refund", 10))]);
+    let tx = net.tx(1, tx_id);
+    assert_eq!(tx.status, TxStatus::Aborted);
+    let steps: Vec<StepStatus> = tx.steps.iter().map(|step| step.status).collect();
+    assert_eq!(steps, vec![StepStatus::Compensated, StepStatus::Failed]);
+    assert!(tx.steps[1].error.as_ref().unwrap().contains("application failed"));
+}
+
+#[test]
+fn a_saga_needs_a_step() {
+    let mut net = Net::new(&[1, 2]);
+    let err = net.execute(1, USER, ExecuteMsg::Input { value: saga(vec![]) }).unwrap_err();
+    assert!(matches!(err, ContractError::EmptySaga {}));
+}
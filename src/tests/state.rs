@@ -0,0 +1,59 @@
+use cosmwasm_std::ContractResult;
+
+use crate::msg::Op;
+use crate::state::TxStatus;
+use crate::tests::{payload, Net, Packet};
+
+#[test]
+fn transitions_follow_the_phases() {
+    assert!(TxStatus::Pending.can_transition_to(TxStatus::Dirty));
+    assert!(TxStatus::Dirty.can_transition_to(TxStatus::Prepared));
+    assert!(TxStatus::Prepared.can_transition_to(TxStatus::PreCommitted));
+    assert!(TxStatus::Prepared.can_transition_to(TxStatus::Committed));
+    assert!(TxStatus::PreCommitted.can_transition_to(TxStatus::Committed));
+
+    // no phase can be skipped, and nothing goes back
+    assert!(!TxStatus::Pending.can_transition_to(TxStatus::Prepared));
+    assert!(!TxStatus::Dirty.can_transition_to(TxStatus::Committed));
+    assert!(!TxStatus::Prepared.can_transition_to(TxStatus::Dirty));
+    assert!(!TxStatus::Dirty.can_transition_to(TxStatus::Pending));
+}
+
+#[test]
+fn final_statuses_never_change() {
+    for status in [TxStatus::Committed, TxStatus::Aborted] {
+        for next in [TxStatus::Pending, TxStatus::Dirty, TxStatus::Prepared, TxStatus::PreCommitted, TxStatus::Committed, TxStatus::Aborted] {
+            assert!(!status.can_transition_to(next), "{} -> {}", status, next);
+        }
+    }
+    // anything in flight can still be aborted
+    for status in [TxStatus::Pending, TxStatus::Dirty, TxStatus::Prepared, TxStatus::PreCommitted] {
+        assert!(status.can_transition_to(TxStatus::Aborted));
+    }
+}
+
+#[test]
+fn a_transaction_goes_through_every_phase_on_every_chain() {
+    let mut net = Net::new(&[1, 2, 3]);
+    let tx_id = net.submit(1, payload());
+    assert_eq!(net.status(1, tx_id), TxStatus::Dirty);
+    // the other chains only join once the announcement reaches them
+    net.run();
+    for chain_id in [1, 2, 3] {
+        let tx = net.tx(chain_id, tx_id);
+        assert_eq!(tx.status, TxStatus::Committed, "chain {}", chain_id);
+        assert!(tx.submitted_at.is_some() && tx.prepared_at.is_some() && tx.committed_at.is_some());
+        assert!(tx.aborted_at.is_none());
+    }
+}
+
+#[test]
+fn a_committed_transaction_cannot_be_aborted() {
+    let mut net = Net::new(&[1, 2]);
+    let tx_id = net.submit(1, payload());
+    net.run();
+
+    let ack = net.relay(Packet::new(2, 1, Op::Abortion { value: tx_id }));
+    assert_eq!(ack, ContractResult::Err("invalid packet: transaction 1-1 cannot move from committed to aborted".to_string()));
+    assert_eq!(net.status(1, tx_id), TxStatus::Committed);
+}
@@ -0,0 +1,47 @@
+use crate::error::ContractError;
+use crate::msg::{ExecuteMsg, Op};
+use crate::state::{TxStatus, TX_PAYLOADS};
+use crate::tests::{payload, Net, Packet, USER};
+
+#[test]
+fn only_a_prepared_transaction_can_be_resolved() {
+    let mut net = Net::new(&[1, 2]);
+    let tx_id = net.submit(1, payload());
+    let err = net.execute(1, USER, ExecuteMsg::Resolve { tx_id }).unwrap_err();
+    assert!(matches!(err, ContractError::CannotResolve { status: TxStatus::Dirty, .. }));
+    assert!(net.queue.iter().all(|packet| !matches!(packet.op(), Op::QueryDecision { .. })));
+}
+
+#[test]
+fn a_prepared_chain_learns_the_commit_of_its_peers() {
+    let mut net = Net::new(&[1, 2, 3]);
+    let tx_id = net.submit(1, payload());
+    let silenced = |packet: &Packet| packet.to == 3 && matches!(packet.op(), Op::PrepareSuccess { .. } | Op::Commit { .. });
+    net.run_only(|packet| !silenced(packet));
+    net.take(silenced);
+    assert_eq!(net.status(1, tx_id), TxStatus::Committed);
+    assert_eq!(net.status(3, tx_id), TxStatus::Prepared);
+
+    let res = net.execute(3, USER, ExecuteMsg::Resolve { tx_id }).unwrap();
+    assert_eq!(res.messages.len(), 2);
+    net.run();
+    assert_eq!(net.status(3, tx_id), TxStatus::Committed);
+    assert!(!TX_PAYLOADS.has(net.storage(3), tx_id));
+}
+
+#[test]
+fn a_peer_that_has_not_prepared_aborts_for_everyone() {
+    let mut net = Net::new(&[1, 2, 3]);
+    let tx_id = net.submit(1, payload());
+    // chain 2 never hears from chain 3, so it is left dirty while the others prepare
+    net.run_only(|packet| !(packet.from == 3 && packet.to == 2));
+    net.take(|_| true);
+    assert_eq!(net.status(1, tx_id), TxStatus::Prepared);
+    assert_eq!(net.status(2, tx_id), TxStatus::Dirty);
+
+    net.execute(1, USER, ExecuteMsg::Resolve { tx_id }).unwrap();
+    net.run();
+    for chain_id in [1, 2, 3] {
+        assert_eq!(net.status(chain_id, tx_id), TxStatus::Aborted, "chain {}", chain_id);
+    }
+}
@@ -0,0 +1,65 @@
+use crate::msg::ExecuteMsg;
+use crate::state::{txs, TxId, TxStatus};
+use crate::tests::{attr, payload, Net, USER};
+
+const PHASE_TIMEOUT: u64 = 60;
+
+fn three_phase(chain_ids: &[u32]) -> Net {
+    Net::with(chain_ids, |msg| msg.three_phase_timeout = Some(PHASE_TIMEOUT))
+}
+
+// relay everything, except that chain 3 hears nothing more once it is pre-committed
+fn pre_commit_chain_3(net: &mut Net, tx_id: TxId) {
+    while let Some(packet) = net.queue.pop_front() {
+        let status = txs().may_load(net.storage(3), tx_id).unwrap().map(|tx| tx.status);
+        if packet.to == 3 && status == Some(TxStatus::PreCommitted) {
+            continue;
+        }
+        net.relay(packet);
+    }
+}
+
+#[test]
+fn every_chain_goes_through_the_pre_commit_phase() {
+    let mut net = three_phase(&[1, 2, 3]);
+    let tx_id = net.submit(1, payload());
+    net.run();
+    for chain_id in [1, 2, 3] {
+        let tx = net.tx(chain_id, tx_id);
+        assert_eq!(tx.status, TxStatus::Committed, "chain {}", chain_id);
+        assert!(tx.pre_committed_at.is_some());
+    }
+}
+
+#[test]
+fn a_pre_committed_chain_commits_on_its_own_once_the_phase_times_out() {
+    let mut net = three_phase(&[1, 2, 3]);
+    let tx_id = net.submit(1, payload());
+    pre_commit_chain_3(&mut net, tx_id);
+    assert_eq!(net.status(3, tx_id), TxStatus::PreCommitted);
+
+    // losing a peer does not stall it either
+    let res = net.close(3, 1);
+    assert_eq!(attr(&res.attributes, "aborted"), Some("0"));
+    assert_eq!(net.status(3, tx_id), TxStatus::PreCommitted);
+
+    let res = net.execute(3, USER, ExecuteMsg::Crank { limit: None }).unwrap();
+    assert_eq!(attr(&res.attributes, "committed"), Some("0"));
+    net.advance(PHASE_TIMEOUT);
+    let res = net.execute(3, USER, ExecuteMsg::Crank { limit: None }).unwrap();
+    assert_eq!(attr(&res.attributes, "committed"), Some("1"));
+    assert_eq!(net.status(3, tx_id), TxStatus::Committed);
+}
+
+#[test]
+fn a_chain_that_times_out_before_the_pre_commit_aborts_on_its_own() {
+    let mut net = three_phase(&[1, 2]);
+    let tx_id = net.submit(1, payload());
+    net.advance(PHASE_TIMEOUT);
+    let res = net.execute(1, USER, ExecuteMsg::Crank { limit: None }).unwrap();
+    assert_eq!(attr(&res.attributes, "aborted"), Some("1"));
+    net.run();
+    for chain_id in [1, 2] {
+        assert_eq!(net.status(chain_id, tx_id), TxStatus::Aborted);
+    }
+}
@@ -0,0 +1,64 @@
+use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_info};
+use cosmwasm_std::{Addr, CosmosMsg, IbcMsg, IbcPacketReceiveMsg, IbcTimeout, IbcTimeoutBlock, SubMsg};
+
+use crate::contract::instantiate;
+use crate::error::ContractError;
+use crate::ibc::ibc_packet_receive;
+use crate::msg::ExecuteMsg;
+use crate::state::{TimeoutPolicy, PACKET_LIFETIME};
+use crate::tests::{instantiate_msg, payload, Net, ADMIN, USER};
+
+// the timeouts of the packets sent by a response
+fn timeouts<T>(msgs: &[SubMsg<T>]) -> Vec<IbcTimeout> {
+    msgs.iter()
+        .filter_map(|msg| match &msg.msg {
+            CosmosMsg::Ibc(IbcMsg::SendPacket { timeout, .. }) => Some(timeout.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn the_packets_time_out_after_the_lifetime_by_default() {
+    let mut net = Net::new(&[1, 2]);
+    let now = net.chain(1).env.block.time;
+    let res = net.execute(1, USER, ExecuteMsg::Input { value: payload() }).unwrap();
+    assert_eq!(timeouts(&res.messages), vec![IbcTimeout::with_timestamp(now.plus_seconds(PACKET_LIFETIME))]);
+}
+
+#[test]
+fn the_config_sets_the_timeout_in_seconds_or_height() {
+    let height = IbcTimeoutBlock { revision: 1, height: 500 };
+    let policy = TimeoutPolicy { seconds: Some(30), height: Some(height) };
+    let mut net = Net::with(&[1, 2], |msg| msg.packet_timeout = Some(policy.clone()));
+    let now = net.chain(1).env.block.time;
+    let res = net.execute(1, USER, ExecuteMsg::Input { value: payload() }).unwrap();
+    assert_eq!(timeouts(&res.messages), vec![IbcTimeout::with_both(height, now.plus_seconds(30))]);
+
+    let mut msg = instantiate_msg(3);
+    msg.packet_timeout = Some(TimeoutPolicy { seconds: None, height: None });
+    let err = instantiate(mock_dependencies().as_mut(), mock_env(), mock_info(ADMIN, &[]), msg).unwrap_err();
+    assert!(matches!(err, ContractError::InvalidTimeout {}));
+}
+
+#[test]
+fn a_transaction_overrides_the_timeout_on_every_chain() {
+    let mut net = Net::new(&[1, 2]);
+    let height = IbcTimeoutBlock { revision: 1, height: 500 };
+    let mut tx = payload();
+    tx.packet_timeout = Some(TimeoutPolicy { seconds: None, height: Some(height) });
+    let res = net.execute(1, USER, ExecuteMsg::Input { value: tx.clone() }).unwrap();
+    assert_eq!(timeouts(&res.messages), vec![IbcTimeout::with_block(height)]);
+
+    // the peer answers within the timeout of the transaction, which the announcement carries
+    let announcement = net.queue.pop_front().unwrap();
+    let chain = net.chain(2);
+    let msg = IbcPacketReceiveMsg::new(announcement.ibc_packet(), Addr::unchecked("relayer"));
+    let res = ibc_packet_receive(chain.deps.as_mut(), chain.env.clone(), msg).unwrap();
+    let sent = timeouts(&res.messages);
+    assert!(!sent.is_empty());
+    assert!(sent.iter().all(|timeout| *timeout == IbcTimeout::with_block(height)));
+
+    tx.packet_timeout = Some(TimeoutPolicy { seconds: None, height: None });
+    assert!(matches!(net.execute(1, USER, ExecuteMsg::Input { value: tx }), Err(ContractError::InvalidTimeout {})));
+}
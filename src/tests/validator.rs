@@ -0,0 +1,48 @@
+use crate::msg::ExecuteMsg;
+use crate::state::TxStatus;
+use crate::tests::{attr, payload, transfer, Net, ADMIN, USER, VALIDATOR};
+
+#[test]
+fn a_chain_refused_by_its_validator_aborts_the_transaction() {
+    let mut net = Net::new(&[1, 2, 3]);
+    net.set_validator(3, |tx| tx.msgs.iter().any(|msgs| msgs.chain_id == 3).then(|| "no funds".to_string()));
+    let mut tx = payload();
+    tx.msgs = vec![transfer(3, 10)];
+    let refused = net.submit(1, tx);
+    let accepted = net.submit(1, payload());
+    net.run();
+
+    for chain_id in [1, 2, 3] {
+        assert_eq!(net.status(chain_id, refused), TxStatus::Aborted);
+        assert_eq!(net.status(chain_id, accepted), TxStatus::Committed);
+    }
+    assert_eq!(net.tx(3, refused).error, Some("no funds".to_string()));
+    assert!(net.executed.is_empty());
+}
+
+#[test]
+fn the_origin_votes_no_when_the_transaction_is_submitted() {
+    let mut net = Net::new(&[1, 2]);
+    net.set_validator(1, |_| Some("paused".to_string()));
+    let res = net.execute(1, USER, ExecuteMsg::Input { value: payload() }).unwrap();
+    assert_eq!(attr(&res.attributes, "vote"), Some("no"));
+    assert_eq!(attr(&res.attributes, "reason"), Some("paused"));
+    let tx_id = net.submit(1, payload());
+    assert_eq!(net.status(1, tx_id), TxStatus::Aborted);
+
+    // without a validator, every transaction is accepted
+    net.execute(1, ADMIN, ExecuteMsg::SetValidator { validator: None }).unwrap();
+    let tx_id = net.submit(1, payload());
+    net.run();
+    assert_eq!(net.status(1, tx_id), TxStatus::Committed);
+}
+
+#[test]
+fn a_validator_that_cannot_answer_refuses() {
+    let mut net = Net::new(&[1, 2]);
+    net.execute(1, ADMIN, ExecuteMsg::SetValidator { validator: Some(VALIDATOR.to_string()) }).unwrap();
+    let tx_id = net.submit(1, payload());
+    let tx = net.tx(1, tx_id);
+    assert_eq!(tx.status, TxStatus::Aborted);
+    assert!(tx.error.is_some());
+}
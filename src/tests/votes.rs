@@ -0,0 +1,117 @@
+use cosmwasm_std::testing::MockStorage;
+use cosmwasm_std::{ContractResult, Timestamp};
+
+use crate::msg::{Op, QueryMsg};
+use crate::state::{TxId, TxStatus, DIRTY_VOTERS, DIRTY_VOTES_MAP, LEGACY_ORIGIN, PREPARE_VOTERS, PREPARE_VOTES_MAP, TX_PAYLOADS};
+use crate::tests::{payload, Net, Packet};
+use crate::utils::{add_vote, recount_votes};
+
+const TX: TxId = TxId { origin: 1, seq: 1 };
+
+#[test]
+fn every_transaction_counts_its_own_votes() {
+    let mut net = Net::new(&[1, 2, 3]);
+    let first = net.submit(1, payload());
+    let second = net.submit(1, payload());
+    assert_eq!(net.tx(1, first).dirty_voters, vec![1]);
+    assert_eq!(net.tx(1, second).dirty_voters, vec![1]);
+
+    net.run_only(|packet| matches!(packet.op(), Op::DirtySuccess { .. }));
+    for chain_id in [1, 2, 3] {
+        for tx_id in [first, second] {
+            let tx = net.tx(chain_id, tx_id);
+            assert_eq!(tx.status, TxStatus::Prepared);
+            assert_eq!(tx.dirty_votes, 3);
+            assert_eq!(tx.dirty_voters, vec![1, 2, 3]);
+        }
+        // only the top of the prepare queue votes, the next transaction waits for it to be settled
+        assert_eq!(net.tx(chain_id, first).prepare_voters, vec![chain_id]);
+        assert_eq!(net.tx(chain_id, second).prepare_votes, 0);
+        let queue: Vec<TxId> = net.query(chain_id, QueryMsg::GetPrepareTx {});
+        assert_eq!(queue, vec![first, second]);
+    }
+
+    net.run();
+    for chain_id in [1, 2, 3] {
+        assert_eq!(net.status(chain_id, first), TxStatus::Committed);
+        assert_eq!(net.status(chain_id, second), TxStatus::Committed);
+        let queue: Vec<TxId> = net.query(chain_id, QueryMsg::GetPrepareTx {});
+        assert!(queue.is_empty());
+    }
+}
+
+#[test]
+fn a_chain_votes_once_per_transaction() {
+    let mut store = MockStorage::new();
+    let time = Timestamp::from_seconds(1);
+    assert!(add_vote(&mut store, &DIRTY_VOTES_MAP, &DIRTY_VOTERS, TX, 2, time).unwrap());
+    // a replayed vote is not counted again
+    assert!(!add_vote(&mut store, &DIRTY_VOTES_MAP, &DIRTY_VOTERS, TX, 2, time).unwrap());
+    assert_eq!(DIRTY_VOTES_MAP.load(&store, TX).unwrap(), 1);
+
+    assert!(add_vote(&mut store, &DIRTY_VOTES_MAP, &DIRTY_VOTERS, TX, 3, time).unwrap());
+    assert_eq!(DIRTY_VOTES_MAP.load(&store, TX).unwrap(), 2);
+
+    // the votes of every phase and of every transaction are kept apart
+    let other = TxId { origin: 2, seq: 1 };
+    assert!(add_vote(&mut store, &PREPARE_VOTES_MAP, &PREPARE_VOTERS, TX, 2, time).unwrap());
+    assert!(add_vote(&mut store, &DIRTY_VOTES_MAP, &DIRTY_VOTERS, other, 2, time).unwrap());
+    assert_eq!(PREPARE_VOTES_MAP.load(&store, TX).unwrap(), 1);
+    assert_eq!(DIRTY_VOTES_MAP.load(&store, TX).unwrap(), 2);
+    assert_eq!(DIRTY_VOTES_MAP.load(&store, other).unwrap(), 1);
+}
+
+#[test]
+fn a_replayed_vote_is_acknowledged_but_not_counted() {
+    let mut net = Net::new(&[1, 2, 3]);
+    let tx_id = net.submit(1, payload());
+    let vote = Packet::new(2, 1, Op::DirtySuccess { value: tx_id, tx: None });
+    assert!(matches!(net.relay(vote.clone()), ContractResult::Ok(_)));
+    assert!(matches!(net.relay(vote), ContractResult::Ok(_)));
+    let tx = net.tx(1, tx_id);
+    assert_eq!(tx.dirty_votes, 2);
+    assert_eq!(tx.dirty_voters, vec![1, 2]);
+}
+
+#[test]
+fn only_the_chain_bound_to_the_channel_votes() {
+    let mut net = Net::new(&[1, 2]);
+    let tx_id = net.submit(1, payload());
+    let ack = net.relay(Packet::new(9, 1, Op::DirtySuccess { value: tx_id, tx: None }));
+    assert_eq!(ack, ContractResult::Err("invalid packet: channel channel-9 is not registered to any chain".to_string()));
+    assert_eq!(net.tx(1, tx_id).dirty_votes, 1);
+}
+
+#[test]
+fn a_channel_cannot_take_over_the_identity_of_another_chain() {
+    let mut net = Net::new(&[1, 2, 3]);
+    let ack = net.relay(Packet::new(3, 1, Op::WhoAmI { chain_id: 2 }));
+    assert_eq!(ack, ContractResult::Err("invalid packet: chain 2 is already bound to channel channel-2".to_string()));
+
+    // a new channel cannot claim a chain that is already bound, nor rebind itself once it is
+    net.connect(1, 9);
+    let ack = net.relay(Packet::new(9, 1, Op::WhoAmI { chain_id: 2 }));
+    assert_eq!(ack, ContractResult::Err("invalid packet: chain 2 is already bound to channel channel-2".to_string()));
+    assert!(matches!(net.relay(Packet::new(9, 1, Op::WhoAmI { chain_id: 9 })), ContractResult::Ok(_)));
+    let ack = net.relay(Packet::new(9, 1, Op::WhoAmI { chain_id: 8 }));
+    assert_eq!(ack, ContractResult::Err("invalid packet: channel channel-9 is already bound to chain 9".to_string()));
+
+    let ack = net.relay(Packet::new(9, 1, Op::WhoAmI { chain_id: LEGACY_ORIGIN }));
+    assert_eq!(ack, ContractResult::Err(format!("invalid packet: chain id {} is reserved for the transactions of 0.0.x", LEGACY_ORIGIN)));
+}
+
+#[test]
+fn votes_of_non_participants_are_dropped_once_the_payload_is_known() {
+    let mut store = MockStorage::new();
+    let time = Timestamp::from_seconds(1);
+    for voter in [1, 2, 3] {
+        add_vote(&mut store, &DIRTY_VOTES_MAP, &DIRTY_VOTERS, TX, voter, time).unwrap();
+    }
+    let mut tx = payload();
+    tx.participants = vec![1, 2];
+    TX_PAYLOADS.save(&mut store, TX, &tx).unwrap();
+    recount_votes(&mut store, TX).unwrap();
+
+    assert_eq!(DIRTY_VOTES_MAP.load(&store, TX).unwrap(), 2);
+    assert!(!DIRTY_VOTERS.has(&store, (TX, 3)));
+}
@@ -1,47 +1,293 @@
-use std::env;
+use cosmwasm_std::{to_json_binary, to_json_vec, Binary, Env, HexBinary, IbcTimeout, Order, StdResult, Storage, SubMsg, Timestamp, WasmMsg};
+use cw_storage_plus::Map;
+use sha2::{Digest, Sha256};
 
-use cosmwasm_std::{Deps, DepsMut, Env, IbcTimeout, Storage, Timestamp};
-
-use crate::{error::{self, ContractError}, state::{Transaction, START_TIME}};
+use crate::{error::ContractError, msg::{ExecuteMsg, Op, PacketData}, state::{txs, Protocol, Role, TimeoutPolicy, Transaction, TxId, TxInfo, TxStatus, CHAINS, CHANNELS, CONFIG, DIRTY_VOTERS, DIRTY_VOTES_MAP, PACKET_LIFETIME, PREPARE_VOTERS, PREPARE_VOTES_MAP, PRE_COMMIT_VOTERS, PRE_COMMIT_VOTES_MAP, REPLY_COUNTER, STATE, TX_PAYLOADS}};
 
 pub fn check_queue_top(
-    queue: Vec<u32>,
-    tx_id: u32,
+    queue: &[TxId],
+    tx_id: TxId,
 ) -> bool {
-    if queue.is_empty() {
-       return false;
+    queue.first() == Some(&tx_id)
+}
+
+
+
+
+
+// the position of the transaction in the queues, by its lamport time and then its origin
+pub fn tx_order(
+    store: &dyn Storage,
+    tx_id: TxId,
+) -> StdResult<(u64, u32, u32)> {
+    let lamport = TX_PAYLOADS.may_load(store, tx_id)?.map(|tx| tx.lamport).unwrap_or_default();
+    Ok((lamport, tx_id.origin, tx_id.seq))
+}
+
+// insert the transaction into the queue at its place in the order every chain agrees on
+pub fn enqueue(
+    store: &dyn Storage,
+    queue: &mut Vec<TxId>,
+    tx_id: TxId,
+) -> StdResult<()> {
+    let order = tx_order(store, tx_id)?;
+    let mut pos = queue.len();
+    for (i, id) in queue.iter().enumerate() {
+        if tx_order(store, *id)? > order {
+            pos = i;
+            break;
+        }
     }
-    if queue[0] == tx_id {
-        return true;
+    queue.insert(pos, tx_id);
+    Ok(())
+}
+
+// advance the lamport clock for a local event, and return the new time
+pub fn tick(store: &mut dyn Storage) -> StdResult<u64> {
+    let mut state = STATE.load(store)?;
+    state.lamport += 1;
+    STATE.save(store, &state)?;
+    Ok(state.lamport)
+}
+
+// catch up with the clock of a received packet
+pub fn merge_clock(store: &mut dyn Storage, lamport: u64) -> StdResult<u64> {
+    let mut state = STATE.load(store)?;
+    state.lamport = state.lamport.max(lamport) + 1;
+    STATE.save(store, &state)?;
+    Ok(state.lamport)
+}
+
+// stamp the packet with the clock of this chain, and with the digest of its transaction if there is one
+pub fn packet_data(store: &mut dyn Storage, op: &Op) -> StdResult<Binary> {
+    let lamport = tick(store)?;
+    let digest = match op.tx_id() {
+        Some(tx_id) => txs().may_load(store, tx_id)?.and_then(|tx| tx.digest),
+        None => None,
+    };
+    to_json_binary(&PacketData { lamport, op: op.clone(), digest })
+}
+
+// add the vote of a chain for the transaction in the given votes map, and return whether it was counted;
+// a chain votes at most once per transaction, so a duplicated or replayed vote is ignored
+pub fn add_vote(
+    store: &mut dyn Storage,
+    votes_map: &Map<TxId, u32>,
+    voters_map: &Map<(TxId, u32), Timestamp>,
+    tx_id: TxId,
+    voter: u32,
+    time: Timestamp,
+) -> StdResult<bool> {
+    if voters_map.has(store, (tx_id, voter)) {
+        return Ok(false);
     }
-    false
+    voters_map.save(store, (tx_id, voter), &time)?;
+    let votes = votes_map.may_load(store, tx_id)?.unwrap_or_default() + 1;
+    votes_map.save(store, tx_id, &votes)?;
+    Ok(true)
 }
 
+// once the payload is known, drop the votes of the chains that do not take part in the transaction,
+// which could only be told apart from the participants by the payload
+pub fn recount_votes(
+    store: &mut dyn Storage,
+    tx_id: TxId,
+) -> StdResult<()> {
+    let participants = match tx_participants(store, tx_id)? {
+        Some(participants) => participants,
+        None => return Ok(()),
+    };
+    let maps = [
+        (&DIRTY_VOTES_MAP, &DIRTY_VOTERS),
+        (&PREPARE_VOTES_MAP, &PREPARE_VOTERS),
+        (&PRE_COMMIT_VOTES_MAP, &PRE_COMMIT_VOTERS),
+    ];
+    for (votes_map, voters_map) in maps {
+        let voters = voters_map
+            .prefix(tx_id)
+            .keys(store, None, None, Order::Ascending)
+            .collect::<StdResult<Vec<u32>>>()?;
+        let mut votes = 0;
+        for voter in voters {
+            if participants.contains(&voter) {
+                votes += 1;
+            } else {
+                voters_map.remove(store, (tx_id, voter));
+            }
+        }
+        votes_map.save(store, tx_id, &votes)?;
+    }
+    Ok(())
+}
 
+// load the record of the transaction, or start a pending one if this chain has never seen it
+pub fn load_tx(
+    store: &dyn Storage,
+    tx_id: TxId,
+    time: Timestamp,
+) -> StdResult<TxInfo> {
+    Ok(txs().may_load(store, tx_id)?.unwrap_or_else(|| TxInfo::new(time)))
+}
 
+// move the transaction to the next status and stamp the time of the change, refusing any illegal transition
+pub fn transition_tx(
+    store: &mut dyn Storage,
+    tx_id: TxId,
+    next: TxStatus,
+    time: Timestamp,
+) -> Result<TxInfo, ContractError> {
+    let mut tx = load_tx(store, tx_id, time)?;
+    if !tx.status.can_transition_to(next) {
+        return Err(ContractError::InvalidTransition { tx_id, from: tx.status, to: next });
+    }
+    tx.status = next;
+    match next {
+        TxStatus::Pending => {},
+        TxStatus::Dirty => tx.submitted_at = Some(time),
+        TxStatus::Prepared => tx.prepared_at = Some(time),
+        TxStatus::PreCommitted => tx.pre_committed_at = Some(time),
+        TxStatus::Committed => tx.committed_at = Some(time),
+        TxStatus::Aborted => tx.aborted_at = Some(time),
+    }
+    txs().save(store, tx_id, &tx)?;
+    Ok(tx)
+}
 
+// find the chain that sits behind a local channel, as registered by the WhoAmI handshake
+pub fn chain_of_channel(
+    store: &dyn Storage,
+    channel_id: &str,
+) -> StdResult<Option<u32>> {
+    CHAINS.may_load(store, channel_id)
+}
+
+// the chains listed by the transaction, none if every member of the vote set takes part or if its payload is not known here yet
+pub fn tx_participants(
+    store: &dyn Storage,
+    tx_id: TxId,
+) -> StdResult<Option<Vec<u32>>> {
+    let participants = match txs().may_load(store, tx_id)?.and_then(|tx| tx.participants) {
+        Some(participants) => Some(participants),
+        None => TX_PAYLOADS.may_load(store, tx_id)?.map(|tx| tx.participants),
+    };
+    Ok(participants.filter(|participants| !participants.is_empty()))
+}
+
+// whether this chain knows who takes part in the transaction, which it learns along with the payload
+pub fn tx_known(
+    store: &dyn Storage,
+    tx_id: TxId,
+) -> StdResult<bool> {
+    let joined = txs().may_load(store, tx_id)?.is_some_and(|tx| tx.participants.is_some());
+    Ok(joined || TX_PAYLOADS.has(store, tx_id))
+}
+
+pub fn tx_role(
+    store: &dyn Storage,
+    tx_id: TxId,
+) -> StdResult<Role> {
+    let role = match CONFIG.load(store)?.protocol {
+        Protocol::AllToAll => Role::Peer,
+        Protocol::Coordinator if tx_id.origin == STATE.load(store)?.chain_id => Role::Coordinator,
+        Protocol::Coordinator => Role::Participant,
+    };
+    Ok(role)
+}
+
+// the channels every message about the transaction is sent on, which lead only to the coordinator for a participant
+pub fn tx_channels(
+    store: &dyn Storage,
+    tx_id: TxId,
+) -> StdResult<Vec<String>> {
+    let state = STATE.load(store)?;
+    let participants = match (tx_role(store, tx_id)?, tx_participants(store, tx_id)?) {
+        (Role::Participant, _) => vec![tx_id.origin],
+        (_, Some(participants)) => participants,
+        (_, None) if tx_known(store, tx_id)? => return Ok(state.channel_ids),
+        // until the payload is known here, only the origin is sure to take part
+        (_, None) => vec![tx_id.origin],
+    };
+    let mut channels = Vec::new();
+    for chain_id in participants {
+        if let Some(channel_id) = CHANNELS.may_load(store, chain_id)? {
+            if state.channel_ids.contains(&channel_id) {
+                channels.push(channel_id);
+            }
+        }
+    }
+    Ok(channels)
+}
+
+// the votes a participant counts in every phase: its own, and the one of the coordinator, which stands for everyone else
+// since the coordinator only votes once it has collected the votes of every participant;
+// the coordinator never sends a PrepareSuccess, so the prepare phase of a participant only ends with the Commit of the coordinator
+const PARTICIPANT_QUORUM: u32 = 2;
 
-pub fn remove_all_pending_elements(
-    queue: &mut Vec<u32>,
-    tx_id: u32,
-) -> Vec<u32>{
-    // remove all elements that are after the element with the given tx_id, not before the elemen
-    if let Some(pos) = queue.iter().position(|tx_id| tx_id == tx_id) {
-        let removed_elements = queue.split_off(pos);
-        removed_elements.into_iter().map(|tx_id| tx_id).collect()
-    } else {
-        vec![]
+// the number of votes the transaction needs in every phase
+pub fn tx_quorum(
+    store: &dyn Storage,
+    tx_id: TxId,
+) -> StdResult<u32> {
+    if tx_role(store, tx_id)? == Role::Participant {
+        return Ok(PARTICIPANT_QUORUM);
     }
+    match tx_participants(store, tx_id)? {
+        Some(participants) => Ok(participants.len() as u32),
+        None => Ok(STATE.load(store)?.node_number),
+    }
+}
+
 
+pub fn get_timeout(env: &Env, policy: &TimeoutPolicy) -> IbcTimeout {
+    let timestamp = policy.seconds.map(|seconds| env.block.time.plus_seconds(seconds));
+    match (policy.height, timestamp) {
+        (Some(block), Some(timestamp)) => IbcTimeout::with_both(block, timestamp),
+        (Some(block), None) => IbcTimeout::with_block(block),
+        (None, Some(timestamp)) => IbcTimeout::with_timestamp(timestamp),
+        (None, None) => IbcTimeout::with_timestamp(env.block.time.plus_seconds(PACKET_LIFETIME)),
+    }
 }
 
+// the timeout of the config, used by the packets that are not about a transaction
+pub fn default_timeout(store: &dyn Storage, env: &Env) -> StdResult<IbcTimeout> {
+    let config = CONFIG.load(store)?;
+    Ok(get_timeout(env, &config.packet_timeout))
+}
 
-const PACKET_LIFETIME: u64 = 3600000;
-pub fn get_timeout(env: &Env) -> IbcTimeout {
-    let timeout = env.block.time.plus_seconds(PACKET_LIFETIME);
-    IbcTimeout::with_timestamp(timeout)
+// the timeout of the packets about a transaction, which may override the one of the config
+pub fn tx_timeout(store: &dyn Storage, env: &Env, tx_id: TxId) -> StdResult<IbcTimeout> {
+    match TX_PAYLOADS.may_load(store, tx_id)?.and_then(|tx| tx.packet_timeout) {
+        Some(policy) => Ok(get_timeout(env, &policy)),
+        None => default_timeout(store, env),
+    }
+}
+
+// the digest binding the id of a transaction to its content, computed the same way on every chain
+pub fn tx_digest(tx_id: TxId, tx: &Transaction) -> StdResult<HexBinary> {
+    let mut hasher = Sha256::new();
+    hasher.update(to_json_vec(tx)?);
+    hasher.update(tx_id.origin.to_be_bytes());
+    hasher.update(tx_id.seq.to_be_bytes());
+    Ok(HexBinary::from(hasher.finalize().as_slice()))
 }
 
 pub fn get_seconds_diff(start: &Timestamp, end: &Timestamp) -> u64 {
-    return end.seconds()-start.seconds();
-} 
+    end.seconds().saturating_sub(start.seconds())
+}
+
+// run a message through the contract itself so that everything it dispatches succeeds or fails as a whole,
+// and get the outcome back in the reply entry point under the returned id
+pub fn call_self(
+    store: &mut dyn Storage,
+    env: &Env,
+    msg: &ExecuteMsg,
+) -> StdResult<SubMsg> {
+    let reply_id = REPLY_COUNTER.may_load(store)?.unwrap_or_default() + 1;
+    REPLY_COUNTER.save(store, &reply_id)?;
+    let msg = WasmMsg::Execute {
+        contract_addr: env.contract.address.to_string(),
+        msg: to_json_binary(msg)?,
+        funds: vec![],
+    };
+    Ok(SubMsg::reply_always(msg, reply_id))
+}